@@ -0,0 +1,47 @@
+// helpers shared by the benches. they're plain binaries (harness = false) to stay on std,
+// run them with `cargo bench --bench <name>`
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use tauri_svelte_synced_store::{NoopSink, StateSyncer, StateSyncerConfig};
+
+// a syncer that emits nowhere, so only the store's own cost is measured
+#[allow(dead_code)]
+pub fn syncer() -> StateSyncer {
+    StateSyncer::with_sink(StateSyncerConfig::default(), NoopSink)
+}
+
+// run f on each of threads threads, iters times, returning the wall time for all of them
+#[allow(dead_code)]
+pub fn concurrent<F: Fn() + Sync>(threads: usize, iters: usize, f: F) -> Duration {
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                for _ in 0..iters {
+                    f();
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+// time iters runs of f on the current thread
+#[allow(dead_code)]
+pub fn serial<T, F: FnMut() -> T>(iters: usize, mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iters {
+        black_box(f());
+    }
+    start.elapsed()
+}
+
+pub fn report(name: &str, elapsed: Duration, ops: usize) {
+    let per_op = elapsed.as_nanos() as f64 / ops as f64;
+    println!(
+        "{:<48} {:>10.1} ns/op  ({} ops in {:?})",
+        name, per_op, ops, elapsed
+    );
+}
@@ -0,0 +1,88 @@
+// updating a large struct. the value is serialized once and that JSON shared by the emit,
+// the disk write (when the key is persisted) and the cache, so an update should cost about
+// one serialization. allocations are counted alongside the time
+
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct Document {
+    title: String,
+    rows: Vec<Row>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct Row {
+    id: u64,
+    label: String,
+    values: Vec<f64>,
+}
+
+const ITERS: usize = 500;
+
+fn document(version: u64) -> Document {
+    Document {
+        title: format!("document {}", version),
+        rows: (0..500)
+            .map(|id| Row {
+                id,
+                label: format!("row {}", id),
+                values: vec![id as f64; 8],
+            })
+            .collect(),
+    }
+}
+
+fn measure<F: FnMut(u64)>(name: &str, mut f: F) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let mut version = 0;
+    let elapsed = common::serial(ITERS, || {
+        version += 1;
+        f(version)
+    });
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    common::report(name, elapsed, ITERS);
+    println!("{:<48} {:>10} allocations/op", "", allocations / ITERS);
+}
+
+fn main() {
+    let syncer = common::syncer();
+    syncer.set("document", document(0));
+
+    // built up front so only the update itself is measured
+    let documents: Vec<Document> = (0..=ITERS as u64).map(document).collect();
+    let mut next = documents.clone().into_iter();
+    measure("update, emitted", |_| {
+        syncer.update("document", next.next().unwrap(), true);
+    });
+
+    // what a single serialization of the document costs on its own, an update should stay
+    // close to one of these rather than one per consumer
+    let mut next = documents.into_iter();
+    measure("one serialization, for scale", |_| {
+        black_box(serde_json::to_value(next.next().unwrap()).unwrap());
+    });
+}
@@ -0,0 +1,61 @@
+// threads each updating and reading a key of their own. the map only guards which keys
+// exist, so they shouldn't wait on each other the way they do behind one Mutex for the
+// whole map. times are wall time per update and read across all threads
+
+mod common;
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct Counter {
+    count: u64,
+}
+
+const ITERS: usize = 50_000;
+
+fn main() {
+    for threads in [1, 2, 4, 8] {
+        let keys: Vec<String> = (0..threads)
+            .map(|thread| format!("key{}", thread))
+            .collect();
+        let ops = threads * ITERS;
+
+        let syncer = common::syncer();
+        for key in &keys {
+            syncer.set(key, Counter::default());
+        }
+        // concurrent runs the closure once per thread, each takes the next key
+        let next = AtomicUsize::new(0);
+        let elapsed = common::concurrent(threads, 1, || {
+            let key = &keys[next.fetch_add(1, Ordering::Relaxed)];
+            for count in 0..ITERS as u64 {
+                syncer.update(key, Counter { count }, true);
+                black_box(syncer.snapshot::<Counter>(key));
+            }
+        });
+        common::report(
+            &format!("update + snapshot, {} threads", threads),
+            elapsed,
+            ops,
+        );
+
+        let map = Mutex::new(HashMap::new());
+        for key in &keys {
+            map.lock().unwrap().insert(key.clone(), Counter::default());
+        }
+        let next = AtomicUsize::new(0);
+        let elapsed = common::concurrent(threads, 1, || {
+            let key = &keys[next.fetch_add(1, Ordering::Relaxed)];
+            for count in 0..ITERS as u64 {
+                map.lock().unwrap().insert(key.clone(), Counter { count });
+                black_box(map.lock().unwrap()[key].clone());
+            }
+        });
+        common::report(&format!("one mutex map, {} threads", threads), elapsed, ops);
+    }
+}
@@ -0,0 +1,45 @@
+// concurrent snapshots of one key, against the same clone-reads through a bare RwLock and a
+// bare Mutex, which is how values were stored before. times are wall time per read across
+// all threads, so reads that don't contend get cheaper as threads are added
+
+mod common;
+
+use std::hint::black_box;
+use std::sync::{Arc, Mutex, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct Readings {
+    samples: Vec<u64>,
+}
+
+const ITERS: usize = 200_000;
+
+fn main() {
+    let value = Readings {
+        samples: (0..64).collect(),
+    };
+    let syncer = common::syncer();
+    syncer.set("readings", value.clone());
+    let rwlock = Arc::new(RwLock::new(value.clone()));
+    let mutex = Arc::new(Mutex::new(value));
+
+    for threads in [1, 2, 4, 8] {
+        let ops = threads * ITERS;
+        let elapsed = common::concurrent(threads, ITERS, || {
+            black_box(syncer.snapshot::<Readings>("readings"));
+        });
+        common::report(&format!("snapshot, {} threads", threads), elapsed, ops);
+
+        let elapsed = common::concurrent(threads, ITERS, || {
+            black_box(rwlock.read().unwrap().clone());
+        });
+        common::report(&format!("rwlock clone, {} threads", threads), elapsed, ops);
+
+        let elapsed = common::concurrent(threads, ITERS, || {
+            black_box(mutex.lock().unwrap().clone());
+        });
+        common::report(&format!("mutex clone, {} threads", threads), elapsed, ops);
+    }
+}
@@ -0,0 +1,113 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Fields, LitStr, parse_macro_input};
+
+// #[derive(SyncedState)], see tauri_svelte_synced_store::SyncedState. each named field is a
+// key, named after the field unless it has #[synced(key = "...")]
+#[proc_macro_derive(SyncedState, attributes(synced))]
+pub fn derive_synced_state(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "SyncedState needs a struct with named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "SyncedState can only be derived for structs",
+            ));
+        }
+    };
+
+    let mut idents = Vec::new();
+    let mut types = Vec::new();
+    let mut keys = Vec::new();
+    for field in fields {
+        let Some(ident) = &field.ident else {
+            continue;
+        };
+        let key = field_key(field)?.unwrap_or_else(|| ident.to_string());
+        if keys.contains(&key) {
+            return Err(syn::Error::new_spanned(
+                field,
+                format!("key \"{}\" is used by more than one field", key),
+            ));
+        }
+        keys.push(key);
+        idents.push(ident);
+        types.push(&field.ty);
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics tauri_svelte_synced_store::SyncedState for #name #ty_generics #where_clause {
+            const KEYS: &'static [&'static str] = &[#(#keys),*];
+
+            fn register<R: tauri::Runtime>(self, syncer: &tauri_svelte_synced_store::StateSyncer<R>) {
+                let Self { #(#idents),* } = self;
+                #(
+                    syncer.load_or::<#types, _>(#keys, move || #idents);
+                )*
+            }
+
+            fn emit<R: tauri::Runtime>(
+                syncer: &tauri_svelte_synced_store::StateSyncer<R>,
+                key: &str,
+            ) -> bool {
+                match key {
+                    #(
+                        #keys => syncer.emit::<#types>(#keys),
+                    )*
+                    _ => false,
+                }
+            }
+
+            fn update<R: tauri::Runtime>(
+                syncer: &tauri_svelte_synced_store::StateSyncer<R>,
+                key: &str,
+                value: &str,
+                version: Option<u128>,
+                emit: bool,
+            ) -> Option<Result<(), tauri_svelte_synced_store::StateError>> {
+                match key {
+                    #(
+                        #keys => Some(syncer.update_typed_string::<#types>(#keys, value, version, emit)),
+                    )*
+                    _ => None,
+                }
+            }
+        }
+    })
+}
+
+// the key from a field's #[synced(key = "...")], if it has one
+fn field_key(field: &syn::Field) -> syn::Result<Option<String>> {
+    let mut key = None;
+    for attr in &field.attrs {
+        if !attr.path().is_ident("synced") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("key") {
+                key = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("unknown synced attribute, expected key = \"...\""))
+            }
+        })?;
+    }
+    Ok(key)
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::lock_recover;
+
+// AuditLog appends a line per change to a key, leaving out the values of sensitive keys:
+//   2025-06-01T12:00:00.000Z key=theme from=none to=4f1c0a9e2b7d3c55 value={"dark":true}
+pub(crate) struct AuditLog {
+    path: PathBuf,
+    // opened on the first change
+    file: Mutex<Option<File>>,
+    // the hash of the value each key was last logged with
+    last: Mutex<HashMap<String, String>>,
+}
+
+impl AuditLog {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        AuditLog {
+            path,
+            file: Default::default(),
+            last: Default::default(),
+        }
+    }
+
+    // log a key's new value, None when it was removed. the value of a sensitive key is
+    // neither written nor hashed
+    pub(crate) fn record(
+        &self,
+        key: &str,
+        value: Option<&Value>,
+        sensitive: bool,
+    ) -> io::Result<()> {
+        let line = if sensitive {
+            format!("{} key={} value=<redacted>\n", timestamp(), key)
+        } else {
+            let to = value.map(hash);
+            let from = {
+                let mut last = lock_recover(&self.last);
+                match &to {
+                    Some(to) => last.insert(key.to_string(), to.clone()),
+                    None => last.remove(key),
+                }
+            };
+            let value = match value {
+                Some(value) => value.to_string(),
+                None => "<removed>".to_string(),
+            };
+            format!(
+                "{} key={} from={} to={} value={}\n",
+                timestamp(),
+                key,
+                from.as_deref().unwrap_or("none"),
+                to.as_deref().unwrap_or("none"),
+                value
+            )
+        };
+
+        let mut file = lock_recover(&self.file);
+        if file.is_none() {
+            *file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            );
+        }
+        match file.as_mut() {
+            Some(file) => file.write_all(line.as_bytes()),
+            None => Ok(()),
+        }
+    }
+}
+
+fn hash(value: &Value) -> String {
+    let digest = Sha256::digest(value.to_string().as_bytes());
+    digest[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// the current time in UTC as RFC 3339 with milliseconds
+fn timestamp() -> String {
+    let since = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = since.as_secs();
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // days since the epoch to a civil date, from Howard Hinnant's chrono-compatible algorithms
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60,
+        since.subsec_millis()
+    )
+}
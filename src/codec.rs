@@ -0,0 +1,81 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+
+use serde::de::Error as _;
+use serde_json::Value;
+
+use crate::{ItemTrait, lock_recover};
+
+// the Codec<T> set with set_serializer for each key, T being the type it was set for
+pub(crate) type CodecMap = Arc<Mutex<HashMap<String, Arc<dyn Any + Send + Sync>>>>;
+
+type EncodeFn<T> = dyn Fn(&T) -> Result<Value, serde_json::Error> + Send + Sync;
+type DecodeFn<T> = dyn Fn(&str) -> Result<T, serde_json::Error> + Send + Sync;
+type DecodeValueFn<T> = dyn Fn(Value) -> Result<T, serde_json::Error> + Send + Sync;
+
+// how a key's values are turned into JSON and back, for emits and the store file alike.
+// serde's unless StateSyncer::set_serializer set other functions for the key
+pub(crate) struct Codec<T> {
+    encode: Arc<EncodeFn<T>>,
+    decode: Arc<DecodeFn<T>>,
+    decode_value: Arc<DecodeValueFn<T>>,
+}
+
+impl<T> Clone for Codec<T> {
+    fn clone(&self) -> Self {
+        Codec {
+            encode: self.encode.clone(),
+            decode: self.decode.clone(),
+            decode_value: self.decode_value.clone(),
+        }
+    }
+}
+
+impl<T: ItemTrait> Codec<T> {
+    pub(crate) fn serde() -> Self {
+        Codec {
+            encode: Arc::new(|value: &T| serde_json::to_value(value)),
+            decode: Arc::new(|s: &str| serde_json::from_str(s)),
+            decode_value: Arc::new(|value: Value| serde_json::from_value(value)),
+        }
+    }
+
+    pub(crate) fn custom<F, G, E>(to: F, from: G) -> Self
+    where
+        F: Fn(&T) -> String + Send + Sync + 'static,
+        G: Fn(&str) -> Result<T, E> + Send + Sync + 'static,
+        E: Display,
+    {
+        let from = Arc::new(move |s: &str| from(s).map_err(serde_json::Error::custom));
+        let from_value = from.clone();
+        Codec {
+            // parsed back, so a to that doesn't return JSON fails here rather than in a window
+            encode: Arc::new(move |value: &T| serde_json::from_str(&to(value))),
+            decode: from,
+            decode_value: Arc::new(move |value: Value| from_value(&value.to_string())),
+        }
+    }
+
+    // the codec set for the key, serde's if there's none or it was set for another type
+    pub(crate) fn of(codecs: &CodecMap, key: &str) -> Self {
+        lock_recover(codecs)
+            .get(key)
+            .and_then(|codec| codec.downcast_ref::<Self>())
+            .cloned()
+            .unwrap_or_else(Self::serde)
+    }
+
+    pub(crate) fn encode(&self, value: &T) -> Result<Value, serde_json::Error> {
+        (self.encode)(value)
+    }
+
+    pub(crate) fn decode(&self, s: &str) -> Result<T, serde_json::Error> {
+        (self.decode)(s)
+    }
+
+    pub(crate) fn decode_value(&self, value: Value) -> Result<T, serde_json::Error> {
+        (self.decode_value)(value)
+    }
+}
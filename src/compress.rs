@@ -0,0 +1,58 @@
+use std::borrow::Cow;
+use std::io;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+use std::io::Read;
+#[cfg(feature = "gzip")]
+use std::io::Write;
+
+// every gzip file starts with its magic number and the deflate method, which neither JSON
+// nor the binary formats start with short of a bincode map with over half a million keys
+#[cfg(feature = "gzip")]
+const GZIP_MAGIC: [u8; 3] = [0x1f, 0x8b, 0x08];
+#[cfg(feature = "zstd")]
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+// Compression is applied to the encoded store file, or to each value before it's sealed
+// with encryption. it's detected by its header on load, so it can be switched freely
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    #[cfg_attr(not(any(feature = "gzip", feature = "zstd")), allow(unused_variables))]
+    pub(crate) fn compress(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::encode_all(bytes, zstd::DEFAULT_COMPRESSION_LEVEL),
+        }
+    }
+}
+
+// the bytes of a store file as its SerdeFormat encoded them (or of a sealed value as it was
+// serialized), whether or not they're compressed
+pub(crate) fn decompress(bytes: &[u8]) -> io::Result<Cow<'_, [u8]>> {
+    #[cfg(feature = "gzip")]
+    if bytes.starts_with(&GZIP_MAGIC) {
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(bytes).read_to_end(&mut decoded)?;
+        return Ok(Cow::Owned(decoded));
+    }
+    #[cfg(feature = "zstd")]
+    if bytes.starts_with(&ZSTD_MAGIC) {
+        let mut decoded = Vec::new();
+        zstd::Decoder::new(bytes)?.read_to_end(&mut decoded)?;
+        return Ok(Cow::Owned(decoded));
+    }
+    Ok(Cow::Borrowed(bytes))
+}
@@ -0,0 +1,77 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::compress::{self, Compression};
+
+const NONCE_LEN: usize = 12;
+
+// EncryptionConfig encrypts every persisted value with ChaCha20-Poly1305 before it's handed
+// to the store, so the store file never holds state in plaintext
+#[derive(Clone)]
+pub struct EncryptionConfig {
+    key: [u8; 32],
+}
+
+impl EncryptionConfig {
+    // the key should be random (e.g. kept in the OS keychain). to start from a passphrase,
+    // derive the key with a salted KDF such as argon2 first
+    pub fn from_key(key: [u8; 32]) -> Self {
+        EncryptionConfig { key }
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.key))
+    }
+
+    // encrypt a value with a fresh nonce, which is stored next to the ciphertext. with
+    // compression the plaintext is compressed first, there's nothing left to gain after
+    pub(crate) fn seal(&self, value: &Value, compression: Option<Compression>) -> Option<Value> {
+        let mut plaintext = serde_json::to_vec(value).ok()?;
+        if let Some(compression) = compression {
+            plaintext = compression.compress(&plaintext).ok()?;
+        }
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self.cipher().encrypt(&nonce, plaintext.as_slice()).ok()?;
+
+        serde_json::to_value(Sealed {
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(ciphertext),
+        })
+        .ok()
+    }
+
+    // None if the value wasn't sealed, or was sealed with a different key
+    pub(crate) fn open(&self, value: Value) -> Option<Value> {
+        let sealed: Sealed = serde_json::from_value(value).ok()?;
+        let nonce = STANDARD.decode(sealed.nonce).ok()?;
+        if nonce.len() != NONCE_LEN {
+            return None;
+        }
+        let ciphertext = STANDARD.decode(sealed.ciphertext).ok()?;
+        let plaintext = self
+            .cipher()
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .ok()?;
+
+        // recognized by its header, so values sealed before compression was turned on still open
+        serde_json::from_slice(&compress::decompress(&plaintext).ok()?).ok()
+    }
+}
+
+// keep the key out of logs
+impl std::fmt::Debug for EncryptionConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionConfig { key: <redacted> }")
+    }
+}
+
+// what an encrypted value looks like in the store
+#[derive(Serialize, Deserialize)]
+struct Sealed {
+    nonce: String,
+    ciphertext: String,
+}
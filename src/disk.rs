@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use serde_json::{Map, Value};
+use tauri::Runtime;
+use tauri_plugin_store::{Error, Store};
+
+use crate::Compression;
+use crate::format::{DeserializeFn, SerializeFn};
+use crate::lock_recover;
+use crate::log::{debug, warn};
+use crate::wal::{Record, Wal};
+
+// DiskFile writes the store file through "{path}.tmp" and a rename so a crash can't
+// truncate it, keeping backups as "{path}.1" up to "{path}.{backups}"
+#[derive(Clone)]
+pub(crate) struct DiskFile {
+    path: PathBuf,
+    serialize: SerializeFn,
+    compression: Option<Compression>,
+    backups: usize,
+    wal: Option<Wal>,
+    // the modification time of the file as of our last save, so the file watcher can tell
+    // our own writes apart from someone else's
+    written: Arc<Mutex<Option<SystemTime>>>,
+}
+
+impl DiskFile {
+    pub(crate) fn new(
+        path: PathBuf,
+        serialize: SerializeFn,
+        compression: Option<Compression>,
+        backups: usize,
+        wal: bool,
+    ) -> Self {
+        DiskFile {
+            wal: wal.then(|| Wal::new(with_suffix(&path, "wal"))),
+            path,
+            serialize,
+            compression,
+            backups,
+            written: Default::default(),
+        }
+    }
+
+    pub(crate) fn logs_writes(&self) -> bool {
+        self.wal.is_some()
+    }
+
+    // record a change to the store in the write-ahead log, if there is one. None deletes
+    pub(crate) fn log(&self, key: &str, value: Option<&Value>, version: u128) -> io::Result<()> {
+        let Some(wal) = &self.wal else {
+            return Ok(());
+        };
+        wal.append(&Record {
+            key: key.to_string(),
+            value: value.cloned(),
+            version,
+        })
+    }
+
+    // apply the changes the write-ahead log has that didn't make it into the store file
+    // before the last run ended, returning how many keys it changed
+    pub(crate) fn replay<R: Runtime>(&self, store: &Store<R>) -> io::Result<usize> {
+        let Some(wal) = &self.wal else {
+            return Ok(0);
+        };
+        let mut replayed = 0;
+        for record in wal.latest()? {
+            if store.get(&record.key) == record.value {
+                continue;
+            }
+            debug!(
+                key = record.key,
+                version = record.version,
+                "replaying logged change"
+            );
+            match record.value {
+                Some(value) => store.set(record.key, value),
+                None => {
+                    store.delete(&record.key);
+                }
+            }
+            replayed += 1;
+        }
+        Ok(replayed)
+    }
+
+    // forget everything logged, for when the store itself is cleared
+    pub(crate) fn clear_log(&self) -> io::Result<()> {
+        match &self.wal {
+            Some(wal) => wal.clear(),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    pub(crate) fn written_at(&self, modified: Option<SystemTime>) -> bool {
+        modified.is_some() && *lock_recover(&self.written) == modified
+    }
+
+    pub(crate) fn save<R: Runtime>(&self, store: &Store<R>) -> Result<(), Error> {
+        let entries: HashMap<_, _> = store.entries().into_iter().collect();
+        let mut bytes = (self.serialize)(&entries).map_err(Error::Serialize)?;
+        if let Some(compression) = self.compression {
+            bytes = compression.compress(&bytes).map_err(Error::Io)?;
+        }
+
+        // nothing changed since the last save, don't rotate a backup out for it
+        if fs::read(&self.path).is_ok_and(|current| current == bytes) {
+            debug!(path = %self.path.display(), "store file unchanged, skipping save");
+            self.compact_log(store);
+            return Ok(());
+        }
+
+        // a failed backup shouldn't keep the current state from being saved
+        if let Err(err) = self.rotate_backups() {
+            warn!(path = %self.path.display(), "failed to rotate backups: {}", err);
+        }
+
+        debug!(path = %self.path.display(), bytes = bytes.len(), "saving store file");
+        write_atomic(&self.path, &bytes).map_err(Error::Io)?;
+        *lock_recover(&self.written) = self.modified();
+        self.compact_log(store);
+        Ok(())
+    }
+
+    // the save went through, a log that didn't shrink only means more to replay
+    fn compact_log<R: Runtime>(&self, store: &Store<R>) {
+        let Some(wal) = &self.wal else {
+            return;
+        };
+        if let Err(err) = wal.compact(|key| store.get(key)) {
+            warn!(path = %self.path.display(), "failed to compact write-ahead log: {}", err);
+        }
+    }
+
+    pub(crate) fn remove_backups(&self) -> io::Result<()> {
+        for (_, backup) in existing_backups(&self.path)? {
+            fs::remove_file(backup)?;
+        }
+        Ok(())
+    }
+
+    // set aside a value that failed to load in "{path}.quarantine", a JSON object by key
+    pub(crate) fn quarantine(&self, key: &str, value: Value) -> io::Result<()> {
+        let mut quarantined = self.quarantined()?;
+        quarantined.insert(key.to_string(), value);
+        write_atomic(
+            &self.quarantine_path(),
+            &serde_json::to_vec_pretty(&quarantined)?,
+        )
+    }
+
+    pub(crate) fn quarantined(&self) -> io::Result<Map<String, Value>> {
+        match fs::read(self.quarantine_path()) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Map::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    // drop a key from quarantine, removing the file once nothing is left in it
+    pub(crate) fn release(&self, key: &str) -> io::Result<()> {
+        let mut quarantined = self.quarantined()?;
+        if quarantined.remove(key).is_none() {
+            return Ok(());
+        }
+        if quarantined.is_empty() {
+            return fs::remove_file(self.quarantine_path());
+        }
+        write_atomic(
+            &self.quarantine_path(),
+            &serde_json::to_vec_pretty(&quarantined)?,
+        )
+    }
+
+    fn quarantine_path(&self) -> PathBuf {
+        with_suffix(&self.path, "quarantine")
+    }
+
+    fn rotate_backups(&self) -> io::Result<()> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+
+        // the oldest backup falls off, along with any left over from a higher backup count
+        for (index, backup) in existing_backups(&self.path)? {
+            if self.backups == 0 || index >= self.backups {
+                fs::remove_file(backup)?;
+            }
+        }
+        if self.backups == 0 {
+            return Ok(());
+        }
+
+        for index in (1..self.backups).rev() {
+            let backup = backup_path(&self.path, index);
+            if backup.exists() {
+                fs::rename(&backup, backup_path(&self.path, index + 1))?;
+            }
+        }
+        // copied rather than moved so the real file stays in place until it's replaced
+        fs::copy(&self.path, backup_path(&self.path, 1))?;
+        Ok(())
+    }
+}
+
+// for a store file that won't load: keep it as "{path}.corrupt" and restore the newest
+// backup that loads, returning its index
+pub(crate) fn restore_backup(path: &Path, deserialize: DeserializeFn) -> io::Result<Option<usize>> {
+    for (index, backup) in existing_backups(path)? {
+        let Ok(bytes) = fs::read(&backup) else {
+            continue;
+        };
+        if let Err(err) = deserialize(&bytes) {
+            warn!(backup = %backup.display(), "backup doesn't load either: {}", err);
+            continue;
+        }
+
+        if path.exists() {
+            fs::rename(path, with_suffix(path, "corrupt"))?;
+        }
+        fs::copy(&backup, path)?;
+        return Ok(Some(index));
+    }
+
+    Ok(None)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn backup_path(path: &Path, index: usize) -> PathBuf {
+    with_suffix(path, &index.to_string())
+}
+
+// every "{path}.{n}" next to path, most recent first
+fn existing_backups(path: &Path) -> io::Result<Vec<(usize, PathBuf)>> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Ok(Vec::new());
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let prefix = format!("{}.", name.to_string_lossy());
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut backups = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let index = file_name
+            .to_string_lossy()
+            .strip_prefix(&prefix)
+            .and_then(|index| index.parse::<usize>().ok());
+        if let Some(index) = index {
+            backups.push((index, entry.path()));
+        }
+    }
+
+    backups.sort_by_key(|(index, _)| *index);
+    Ok(backups)
+}
+
+pub(crate) fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let tmp = with_suffix(path, "tmp");
+
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    if let Some(dir) = dir {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    // the data has to be on disk before the rename, or a crash could leave an empty file
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp, path)?;
+
+    // make the rename itself durable
+    #[cfg(unix)]
+    if let Some(dir) = dir {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use std::collections::HashMap;
+
+// Recency tracks when each key was last used, so the least recently used can be evicted
+// once max_keys is exceeded
+#[derive(Default)]
+pub(crate) struct Recency {
+    tick: u64,
+    used: HashMap<String, u64>,
+}
+
+impl Recency {
+    pub(crate) fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        match self.used.get_mut(key) {
+            Some(used) => *used = self.tick,
+            None => {
+                self.used.insert(key.to_string(), self.tick);
+            }
+        }
+    }
+
+    pub(crate) fn forget(&mut self, key: &str) {
+        self.used.remove(key);
+    }
+
+    // the least recently used key that can be evicted, if any
+    pub(crate) fn least_recent(&self, evictable: impl Fn(&str) -> bool) -> Option<String> {
+        self.used
+            .iter()
+            .filter(|(key, _)| evictable(key))
+            .min_by_key(|(_, used)| **used)
+            .map(|(key, _)| key.clone())
+    }
+}
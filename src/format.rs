@@ -0,0 +1,128 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+use serde_json::Value;
+
+use crate::compress;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+pub(crate) type SerializeFn = fn(&HashMap<String, Value>) -> Result<Vec<u8>, BoxError>;
+pub(crate) type DeserializeFn = fn(&[u8]) -> Result<HashMap<String, Value>, BoxError>;
+
+// SerdeFormat is how the store file is encoded on disk, events are always JSON
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SerdeFormat {
+    #[default]
+    Json,
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    #[cfg(feature = "bincode")]
+    Bincode,
+}
+
+impl SerdeFormat {
+    // pretty only changes how JSON is laid out, the binary formats ignore it
+    pub(crate) fn serialize_fn(self, pretty: bool) -> SerializeFn {
+        match self {
+            SerdeFormat::Json if pretty => json_serialize_pretty,
+            SerdeFormat::Json => json_serialize,
+            #[cfg(feature = "msgpack")]
+            SerdeFormat::MessagePack => msgpack_serialize,
+            #[cfg(feature = "bincode")]
+            SerdeFormat::Bincode => bincode_serialize,
+        }
+    }
+
+    pub(crate) fn deserialize_fn(self) -> DeserializeFn {
+        match self {
+            SerdeFormat::Json => json_deserialize,
+            #[cfg(feature = "msgpack")]
+            SerdeFormat::MessagePack => msgpack_deserialize,
+            #[cfg(feature = "bincode")]
+            SerdeFormat::Bincode => bincode_deserialize,
+        }
+    }
+}
+
+// keys are written sorted so the same state always encodes to the same bytes. objects
+// inside the values keep serde_json's own ordering, which is stable for a given value
+fn json_serialize(map: &HashMap<String, Value>) -> Result<Vec<u8>, BoxError> {
+    let sorted: BTreeMap<_, _> = map.iter().collect();
+    Ok(serde_json::to_vec(&sorted)?)
+}
+
+fn json_serialize_pretty(map: &HashMap<String, Value>) -> Result<Vec<u8>, BoxError> {
+    let sorted: BTreeMap<_, _> = map.iter().collect();
+    Ok(serde_json::to_vec_pretty(&sorted)?)
+}
+
+fn json_deserialize(bytes: &[u8]) -> Result<HashMap<String, Value>, BoxError> {
+    let bytes = compress::decompress(bytes)?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+#[cfg(feature = "msgpack")]
+fn msgpack_serialize(map: &HashMap<String, Value>) -> Result<Vec<u8>, BoxError> {
+    Ok(rmp_serde::to_vec_named(map)?)
+}
+
+#[cfg(feature = "msgpack")]
+fn msgpack_deserialize(bytes: &[u8]) -> Result<HashMap<String, Value>, BoxError> {
+    let bytes = compress::decompress(bytes)?;
+    Ok(rmp_serde::from_slice(&bytes)?)
+}
+
+// bincode isn't self-describing so it can't decode a serde_json::Value directly,
+// each value is kept as JSON text inside the bincode encoded map instead
+#[cfg(feature = "bincode")]
+fn bincode_serialize(map: &HashMap<String, Value>) -> Result<Vec<u8>, BoxError> {
+    let encoded = map
+        .iter()
+        .map(|(key, value)| Ok((key.as_str(), serde_json::to_string(value)?)))
+        .collect::<Result<HashMap<&str, String>, serde_json::Error>>()?;
+    Ok(bincode::serialize(&encoded)?)
+}
+
+#[cfg(feature = "bincode")]
+fn bincode_deserialize(bytes: &[u8]) -> Result<HashMap<String, Value>, BoxError> {
+    let bytes = compress::decompress(bytes)?;
+    let encoded: HashMap<String, String> = bincode::deserialize(&bytes)?;
+    encoded
+        .into_iter()
+        .map(|(key, value)| Ok((key, serde_json::from_str(&value)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(format: SerdeFormat, pretty: bool) {
+        let map: HashMap<String, Value> = [
+            ("counter".to_string(), serde_json::json!({ "count": 3 })),
+            ("tags".to_string(), serde_json::json!(["a", "b"])),
+            ("empty".to_string(), Value::Null),
+        ]
+        .into();
+        let bytes = format.serialize_fn(pretty)(&map).unwrap();
+        assert_eq!(format.deserialize_fn()(&bytes).unwrap(), map);
+    }
+
+    #[test]
+    fn json_round_trips() {
+        round_trip(SerdeFormat::Json, false);
+        round_trip(SerdeFormat::Json, true);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack_round_trips() {
+        round_trip(SerdeFormat::MessagePack, false);
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode_round_trips() {
+        round_trip(SerdeFormat::Bincode, false);
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde_json::Value;
+
+// History keeps the recent values of each key for undo and redo, as JSON so it doesn't need
+// to know their types. each key keeps up to depth values before its current one
+pub(crate) struct History {
+    depth: usize,
+    by_key: HashMap<String, Timeline>,
+}
+
+struct Timeline {
+    states: VecDeque<Value>,
+    // index into states of the value the key currently holds
+    current: usize,
+}
+
+impl History {
+    pub(crate) fn new(depth: usize) -> Self {
+        History {
+            depth,
+            by_key: HashMap::new(),
+        }
+    }
+
+    // record a key's new value, dropping anything undone and anything past depth
+    pub(crate) fn record(&mut self, key: &str, value: Value) {
+        if self.depth == 0 {
+            return;
+        }
+        let timeline = self.by_key.entry(key.to_string()).or_insert(Timeline {
+            states: VecDeque::new(),
+            current: 0,
+        });
+
+        if timeline.states.get(timeline.current) == Some(&value) {
+            return;
+        }
+        if !timeline.states.is_empty() {
+            timeline.states.truncate(timeline.current + 1);
+        }
+        timeline.states.push_back(value);
+        if timeline.states.len() > self.depth + 1 {
+            timeline.states.pop_front();
+        }
+        timeline.current = timeline.states.len() - 1;
+    }
+
+    // step back to the previous value, None if there isn't one
+    pub(crate) fn undo(&mut self, key: &str) -> Option<Value> {
+        let timeline = self.by_key.get_mut(key)?;
+        if timeline.current == 0 {
+            return None;
+        }
+        timeline.current -= 1;
+        timeline.states.get(timeline.current).cloned()
+    }
+
+    // step forward to the value last undone, None if nothing was undone since the last change
+    pub(crate) fn redo(&mut self, key: &str) -> Option<Value> {
+        let timeline = self.by_key.get_mut(key)?;
+        if timeline.current + 1 >= timeline.states.len() {
+            return None;
+        }
+        timeline.current += 1;
+        timeline.states.get(timeline.current).cloned()
+    }
+
+    pub(crate) fn forget(&mut self, key: &str) {
+        self.by_key.remove(key);
+    }
+}
@@ -1,15 +1,88 @@
 use serde::de::{DeserializeOwned, Error};
 use serde::{Deserialize, Serialize};
 use specta::Type;
+use std::borrow::Cow;
 use std::fmt::Debug;
-use std::sync::{LazyLock, LockResult, MutexGuard};
+use std::ops::{Deref, DerefMut};
+use std::sync::{
+    LazyLock, LockResult, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    TryLockError, TryLockResult,
+};
 use std::{
-    any::Any,
-    collections::HashMap,
-    pin::Pin,
-    sync::{Arc, Mutex},
+    any::{Any, TypeId},
+    collections::{HashMap, HashSet, hash_map::Entry},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Duration,
 };
 
+use log::{debug, error, info, key_span, warn};
+use tauri::{AppHandle, Runtime, Wry};
+use tauri_plugin_store::{Store, StoreExt};
+use tauri_specta::Event;
+
+mod audit;
+mod codec;
+mod compress;
+mod crypto;
+mod disk;
+mod evict;
+mod format;
+mod history;
+#[doc(hidden)]
+pub mod log;
+mod middleware;
+mod migrate;
+mod observe;
+mod owned;
+mod patch;
+mod persist;
+mod scope;
+mod sink;
+mod synced;
+#[cfg(test)]
+mod tests;
+// the SyncedState derive names the crate by path, which tests.rs needs to resolve too
+#[cfg(all(test, feature = "derive"))]
+extern crate self as tauri_svelte_synced_store;
+mod txn;
+mod wal;
+mod watch;
+
+use audit::AuditLog;
+use codec::{Codec, CodecMap};
+pub use compress::Compression;
+pub use crypto::EncryptionConfig;
+use disk::DiskFile;
+use evict::Recency;
+pub use format::SerdeFormat;
+use history::History;
+use middleware::MiddlewareFn;
+pub use middleware::MiddlewareResult;
+use migrate::Migrations;
+pub use observe::Subscription;
+use observe::{AnyChangeFn, ChangeFn, Observers};
+pub use owned::OwnedItem;
+use persist::PersistWorker;
+pub use scope::ScopedSyncer;
+pub use sink::{EventSink, NoopSink, TauriSink};
+#[cfg(any(test, feature = "test-util"))]
+pub use sink::{RecordedEvent, RecordingSink};
+pub use synced::SyncedState;
+#[cfg(feature = "derive")]
+pub use tauri_svelte_synced_store_derive::SyncedState;
+use txn::Staged;
+pub use txn::Txn;
+
+// used by state_handlers! to name the commands for a named syncer
+#[doc(hidden)]
+pub use paste;
+// used by state_events! for the patch and batch_update payloads
+#[doc(hidden)]
+pub use serde_json;
+
 static VERBOSE_LOG: LazyLock<bool> = LazyLock::new(|| std::env::var("RUST_DEBUG").is_ok());
 
 pub fn debug_val<T: Debug>(val: &T) -> String {
@@ -19,376 +92,4179 @@ pub fn debug_val<T: Debug>(val: &T) -> String {
         "<...>".to_string()
     }
 }
-use tauri::{AppHandle, Emitter};
-use tauri_plugin_store::{Store, StoreExt};
-use tauri_specta::Event;
-use tracing::{debug, error, info, warn};
+
+// lock a mutex, recovering the value if a previous holder panicked so one bad
+// closure doesn't take down every later access to the same key
+fn lock_recover<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| {
+        warn!("recovering from poisoned lock");
+        err.into_inner()
+    })
+}
+
+// lock_recover for the data map and the value of each key, which are behind RwLocks so
+// lookups, snapshots and emits can run concurrently and only writers wait on each other
+fn read_recover<T: ?Sized>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|err| {
+        warn!("recovering from poisoned lock");
+        err.into_inner()
+    })
+}
+
+fn write_recover<T: ?Sized>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|err| {
+        warn!("recovering from poisoned lock");
+        err.into_inner()
+    })
+}
+
+// encrypt a value on its way to disk if encryption is configured, None if that failed
+fn seal(
+    encryption: Option<&EncryptionConfig>,
+    compression: Option<Compression>,
+    value: serde_json::Value,
+) -> Option<serde_json::Value> {
+    match encryption {
+        Some(encryption) => {
+            let sealed = encryption.seal(&value, compression);
+            if sealed.is_none() {
+                error!("failed to encrypt value, skipping disk write");
+            }
+            sealed
+        }
+        None => Some(value),
+    }
+}
+
+// how many updates a slow subscribe receiver can fall behind before it starts missing them
+#[cfg(feature = "tokio")]
+const UPDATES_CAPACITY: usize = 256;
+
+// how long the first emit retry waits, doubling for each one after it up to the max
+const EMIT_RETRY_BACKOFF: Duration = Duration::from_millis(10);
+const EMIT_RETRY_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+// unique per syncer instance, even across syncers created in the same process
+fn new_origin_id() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let started = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!(
+        "{:x}-{:x}-{:x}",
+        std::process::id(),
+        started,
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+// a key paired with the error it failed with
+pub type KeyError = (String, serde_json::Error);
+
+#[derive(Debug)]
+pub enum StateError {
+    KeyNotFound(String),
+    TypeMismatch(String),
+    Serialize(String, serde_json::Error),
+    Deserialize(String, serde_json::Error),
+    Persist(tauri_plugin_store::Error),
+    WindowNotFound(String),
+    DecryptionFailed(String),
+    // every key that failed to serialize, along with why
+    SerializeFailed(Vec<KeyError>),
+    // the value on disk is at a schema version no registered migration upgrades from
+    MigrationRequired(String, u32),
+    Emit(tauri::Error),
+    // a panic while the key was locked may have left its value half-updated
+    Poisoned(String),
+    // the update carried an older version than the key's current one
+    Stale(String),
+    // a validator or middleware rejected the value, with the reason it gave
+    Invalid(String, String),
+}
+
+impl std::fmt::Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::KeyNotFound(key) => write!(f, "key not found: {}", key),
+            StateError::TypeMismatch(key) => write!(f, "type mismatch for key: {}", key),
+            StateError::Serialize(key, err) => {
+                write!(f, "failed to serialize value for key {}: {}", key, err)
+            }
+            StateError::Deserialize(key, err) => {
+                write!(f, "failed to deserialize value for key {}: {}", key, err)
+            }
+            StateError::Persist(err) => write!(f, "failed to persist state: {}", err),
+            StateError::WindowNotFound(label) => write!(f, "no window with label: {}", label),
+            StateError::DecryptionFailed(key) => {
+                write!(f, "failed to decrypt value for key: {}", key)
+            }
+            StateError::SerializeFailed(failed) => {
+                let keys: Vec<&str> = failed.iter().map(|(key, _)| key.as_str()).collect();
+                write!(f, "failed to serialize keys: {}", keys.join(", "))
+            }
+            StateError::MigrationRequired(key, version) => write!(
+                f,
+                "no migration for key {} from schema version {}",
+                key, version
+            ),
+            StateError::Emit(err) => write!(f, "failed to emit event: {}", err),
+            StateError::Poisoned(key) => write!(f, "lock poisoned for key: {}", key),
+            StateError::Stale(key) => write!(f, "stale update for key: {}", key),
+            StateError::Invalid(key, reason) => {
+                write!(f, "invalid value for key {}: {}", key, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StateError::Serialize(_, err) => Some(err),
+            StateError::Deserialize(_, err) => Some(err),
+            StateError::Persist(err) => Some(err),
+            StateError::Emit(err) => Some(err),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Deserialize, Serialize, Type, Clone, Debug, Event)]
 pub struct StateUpdate {
     pub version: Option<u128>,
     pub name: String,
     pub value: String,
+    // the syncer that produced this update, if it came from the backend
+    pub origin: Option<String>,
+}
+
+// the payload emitted to the frontend for a key, tagged with the key's current version
+#[derive(Deserialize, Serialize, Type, Clone, Debug)]
+pub struct VersionedValue<T> {
+    pub version: u128,
+    pub value: T,
+    pub origin: String,
+    // counts up across every event the syncer sends, so the frontend can spot events that
+    // arrive out of order. optional so payloads from older versions still deserialize
+    #[serde(default)]
+    pub seq: Option<u64>,
+    // when the event was sent, in milliseconds since the unix epoch
+    #[serde(default)]
+    pub timestamp: Option<u64>,
 }
 
 // Define an alias trait that combines all the required traits
+#[cfg(not(feature = "schema"))]
 pub trait ItemTrait: 'static + Send + Sync + Serialize + DeserializeOwned + Debug + Clone {}
 // Blanket impl
-impl<'r, T> ItemTrait for T where
-    T: 'static + Send + Sync + Serialize + DeserializeOwned + Debug + Clone
+#[cfg(not(feature = "schema"))]
+impl<T> ItemTrait for T where T: 'static + Send + Sync + Serialize + DeserializeOwned + Debug + Clone
+{}
+
+// with the schema feature every state type also has to implement JsonSchema, see schema_for
+#[cfg(feature = "schema")]
+pub trait ItemTrait:
+    'static + Send + Sync + Serialize + DeserializeOwned + Debug + Clone + schemars::JsonSchema
+{
+}
+#[cfg(feature = "schema")]
+impl<T> ItemTrait for T where
+    T: 'static + Send + Sync + Serialize + DeserializeOwned + Debug + Clone + schemars::JsonSchema
 {
 }
 
+// Counter is implemented for the integer types fetch_add and fetch_sub work on.
+// both saturate at the type's bounds rather than wrapping
+pub trait Counter: ItemTrait + Copy {
+    fn add(self, delta: Self) -> Self;
+    fn sub(self, delta: Self) -> Self;
+}
+
+macro_rules! impl_counter {
+    ($($t:ty),*) => {
+        $(
+            impl Counter for $t {
+                fn add(self, delta: Self) -> Self {
+                    self.saturating_add(delta)
+                }
+
+                fn sub(self, delta: Self) -> Self {
+                    self.saturating_sub(delta)
+                }
+            }
+        )*
+    };
+}
+
+impl_counter!(
+    i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize
+);
+
+// how often lock_timeout checks whether the key has been unlocked
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// Item wraps an object and emits an update of the wrapped object when Item is dropped
+// the object is expected to be wrapped in an RwLock
 // Item wraps an object and emits an update of the wrapped object when Item is dropped
-// the object is expected to be wrapped in a mutex
-pub struct Item<'r, T: ItemTrait>(
-    &'r Mutex<T>,               // 0: value
-    &'r str,                    // 1: key
-    &'r AppHandle,              // 2: tauri app ref
-    &'r bool,                   // 3: save_to_disk
-    &'r Arc<Store<tauri::Wry>>, // 4: disk_store
+// the object is expected to be wrapped in an RwLock
+// note: only an Item that was modified (through its guard or mark_dirty) emits on drop
+pub struct Item<'r, T: ItemTrait, R: Runtime = Wry>(
+    Arc<RwLock<T>>,     // 0: value
+    Cow<'r, str>,       // 1: key, owned when it was qualified by a ScopedSyncer
+    &'r StateSyncer<R>, // 2: owning syncer
+    AtomicBool,         // 3: dirty
+    bool,               // 4: staged, not stored until it's dropped, see StateSyncer::stage
 );
 
-impl<'r, T: ItemTrait> Item<'r, T> {
-    pub fn lock(&'_ self) -> LockResult<MutexGuard<'_, T>> {
-        self.0.lock()
+impl<'r, T: ItemTrait, R: Runtime> Item<'r, T, R> {
+    pub fn lock(&'_ self) -> LockResult<ItemGuard<'_, T>> {
+        lock_value(self.2, &self.1, &self.0, &self.3)
+    }
+
+    // like lock, but fails with WouldBlock instead of waiting if the key is locked, e.g. by
+    // another Item for the same key held further up the stack
+    pub fn try_lock(&'_ self) -> TryLockResult<ItemGuard<'_, T>> {
+        try_lock_value(self.2, &self.1, &self.0, &self.3)
+    }
+
+    // like try_lock, but keeps trying until timeout is up before failing with WouldBlock
+    pub fn lock_timeout(&'_ self, timeout: Duration) -> TryLockResult<ItemGuard<'_, T>> {
+        lock_value_timeout(self.2, &self.1, &self.0, &self.3, timeout)
+    }
+
+    // force an emit on drop, e.g. after changing the value through interior mutability
+    pub fn mark_dirty(&self) {
+        self.3.store(true, Ordering::Relaxed);
     }
 }
 
-impl<'r, T: ItemTrait> Drop for Item<'r, T> {
-    fn drop(&mut self) {
-        let self_guard = self.0.lock().unwrap();
-        debug!("[Item] dropped: {}", debug_val(&*self_guard));
+// the locking behind Item and OwnedItem
+fn lock_value<'g, T: ItemTrait, R: Runtime>(
+    syncer: &StateSyncer<R>,
+    key: &str,
+    value: &'g RwLock<T>,
+    dirty: &'g AtomicBool,
+) -> LockResult<ItemGuard<'g, T>> {
+    // the value may be changed through the guard, the cached JSON can't be trusted anymore
+    syncer.forget_json(key);
+    match value.write() {
+        Ok(guard) => Ok(item_guard(syncer, key, guard, dirty)),
+        Err(err) => Err(PoisonError::new(item_guard(
+            syncer,
+            key,
+            err.into_inner(),
+            dirty,
+        ))),
+    }
+}
 
-        let name = format!("{}_update", self.1);
-        self.2
-            .emit(&name, self_guard.clone())
-            .expect("unable to emit state");
+fn try_lock_value<'g, T: ItemTrait, R: Runtime>(
+    syncer: &StateSyncer<R>,
+    key: &str,
+    value: &'g RwLock<T>,
+    dirty: &'g AtomicBool,
+) -> TryLockResult<ItemGuard<'g, T>> {
+    let guard = match value.try_write() {
+        Ok(guard) => Ok(item_guard(syncer, key, guard, dirty)),
+        Err(TryLockError::Poisoned(err)) => Err(TryLockError::Poisoned(PoisonError::new(
+            item_guard(syncer, key, err.into_inner(), dirty),
+        ))),
+        Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+    };
+    if !matches!(guard, Err(TryLockError::WouldBlock)) {
+        syncer.forget_json(key);
+    }
+    guard
+}
 
-        // if disk persist is enabled
-        if *self.3 {
-            debug!("[Item] persisting to disk: {}", self.1);
-            self.4.set(self.1, serde_json::json!(*self_guard));
+fn lock_value_timeout<'g, T: ItemTrait, R: Runtime>(
+    syncer: &StateSyncer<R>,
+    key: &str,
+    value: &'g RwLock<T>,
+    dirty: &'g AtomicBool,
+    timeout: Duration,
+) -> TryLockResult<ItemGuard<'g, T>> {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        match try_lock_value(syncer, key, value, dirty) {
+            Err(TryLockError::WouldBlock) if std::time::Instant::now() < deadline => {
+                std::thread::sleep(LOCK_POLL_INTERVAL);
+            }
+            result => return result,
+        }
+    }
+}
+
+// until the first change the value is still what the syncer last stored, keep it in case
+// the validator rejects what the guard is used for
+fn item_guard<'g, T: ItemTrait, R: Runtime>(
+    syncer: &StateSyncer<R>,
+    key: &str,
+    guard: RwLockWriteGuard<'g, T>,
+    dirty: &'g AtomicBool,
+) -> ItemGuard<'g, T> {
+    if !dirty.load(Ordering::Relaxed) && syncer.has_validator(key) {
+        lock_recover(&syncer.rollback).insert(key.to_string(), Box::new(guard.clone()));
+    }
+    ItemGuard(guard, dirty)
+}
+
+// ItemGuard gives access to the value locked by Item::lock, marking the Item dirty
+// the first time the value is mutably borrowed
+pub struct ItemGuard<'g, T: ItemTrait>(RwLockWriteGuard<'g, T>, &'g AtomicBool);
+
+impl<'g, T: ItemTrait> Deref for ItemGuard<'g, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<'g, T: ItemTrait> DerefMut for ItemGuard<'g, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.1.store(true, Ordering::Relaxed);
+        &mut self.0
+    }
+}
+
+// ReadItem is a read-only handle to a key from get_ref. unlike Item it never emits or
+// persists, there's no way to change the value through it
+pub struct ReadItem<T: ItemTrait>(Arc<RwLock<T>>);
+
+impl<T: ItemTrait> ReadItem<T> {
+    pub fn lock(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+        self.0.read()
+    }
+
+    pub fn try_lock(&self) -> TryLockResult<RwLockReadGuard<'_, T>> {
+        self.0.try_read()
+    }
+}
+
+impl<T: ItemTrait> Clone for ReadItem<T> {
+    fn clone(&self) -> Self {
+        ReadItem(self.0.clone())
+    }
+}
+
+impl<'r, T: ItemTrait, R: Runtime> Drop for Item<'r, T, R> {
+    fn drop(&mut self) {
+        let key = self.1.as_ref();
+        let _span = key_span!(key, T);
+        if self.4 {
+            self.2.store_staged(key, &self.0);
+            return;
         }
+        if !self.3.load(Ordering::Relaxed) {
+            debug!("[Item] dropped unmodified, skipping emit: {}", key);
+            return;
+        }
+        self.2.sync_value(key, &self.0);
     }
 }
 
-impl<'r, T: ItemTrait> Clone for Item<'r, T> {
+impl<'r, T: ItemTrait, R: Runtime> Clone for Item<'r, T, R> {
     fn clone(&self) -> Self {
-        Item(self.0, self.1, self.2, self.3, self.4)
+        Item(
+            self.0.clone(),
+            self.1.clone(),
+            self.2,
+            AtomicBool::new(self.3.load(Ordering::Relaxed)),
+            self.4,
+        )
     }
 }
 
-impl<'r, T: ItemTrait + PartialEq> PartialEq for Item<'r, T> {
+impl<'r, T: ItemTrait + PartialEq, R: Runtime> PartialEq for Item<'r, T, R> {
     fn eq(&self, other: &Self) -> bool {
-        let self_guard = self.0.lock().unwrap();
-        let other_guard = other.0.lock().unwrap();
+        let self_guard = read_recover(&self.0);
+        let other_guard = read_recover(&other.0);
         self_guard.eq(&other_guard) && self.1 == other.1
     }
 }
 
+type FromStrFn = dyn Fn(&str) -> Result<Box<dyn Any + Send>, serde_json::Error> + Send;
+// serializes the value in a stored lock for the key
+type ToValueFn =
+    dyn Fn(&(dyn Any + Send + Sync)) -> Result<serde_json::Value, serde_json::Error> + Send;
+// swaps a value produced by from_str into the stored lock for the key
+type ReplaceFn = dyn Fn(&(dyn Any + Send + Sync), Box<dyn Any + Send>) -> bool + Send;
+// wraps a value produced by from_str into a new entry for the data map
+type WrapFn = dyn Fn(Box<dyn Any + Send>) -> Option<Arc<dyn Any + Send + Sync>> + Send;
+// merge a patch into the key's lock, returning the new JSON, a copy of the value and the
+// version bump gave it under the lock. only stored if validate accepts it
+type PatchFn = dyn Fn(
+        &(dyn Any + Send + Sync),
+        &str,
+        &serde_json::Value,
+        &CheckFn,
+        &BumpFn,
+    ) -> Result<Patched, StateError>
+    + Send;
+type CheckFn<'f> = dyn Fn(&dyn Any) -> Result<(), StateError> + 'f;
+type BumpFn<'f> = dyn Fn() -> u128 + 'f;
+type Patched = (serde_json::Value, Box<dyn Any + Send>, u128);
+// read locks the value in a stored lock for the key, None if it holds a different type
+type ReadLockFn =
+    dyn for<'e> Fn(&'e (dyn Any + Send + Sync)) -> Option<Box<dyn LockedValue + 'e>> + Send;
+
+// a key's value held read locked, so snapshot_many can lock every key before it reads any
+trait LockedValue {
+    fn to_value(&self) -> Result<serde_json::Value, serde_json::Error>;
+}
+
+impl<T: ItemTrait> LockedValue for (RwLockReadGuard<'_, T>, Codec<T>) {
+    fn to_value(&self) -> Result<serde_json::Value, serde_json::Error> {
+        self.1.encode(&self.0)
+    }
+}
+
+// pins down the closure's signature, which can't be inferred with the guard borrowing
+// from the entry
+fn read_lock_fn<F>(f: F) -> F
+where
+    F: for<'e> Fn(&'e (dyn Any + Send + Sync)) -> Option<Box<dyn LockedValue + 'e>>,
+{
+    f
+}
+
 struct Serializers {
-    _from_str: Box<dyn Fn(&str) -> Result<Box<dyn Any + Send>, serde_json::Error> + Send>,
-    _to_str: Box<dyn Fn(&dyn Any) -> Result<String, serde_json::Error> + Send>,
+    type_id: TypeId,
+    type_name: &'static str,
+    from_str: Box<FromStrFn>,
+    to_value: Box<ToValueFn>,
+    replace: Box<ReplaceFn>,
+    wrap: Box<WrapFn>,
+    patch: Box<PatchFn>,
+    read_lock: Box<ReadLockFn>,
+    // whether a stored lock holds this type, so replace can be checked before it's called
+    holds: fn(&(dyn Any + Send + Sync)) -> bool,
+    #[cfg(feature = "schema")]
+    schema: fn() -> serde_json::Value,
+}
+
+impl Serializers {
+    fn with_codec<T: ItemTrait>(codec: Codec<T>) -> Self {
+        let from = codec.clone();
+        let deserializer = move |s: &str| -> Result<Box<dyn Any + Send>, serde_json::Error> {
+            debug!(type = std::any::type_name:: <T>(), "deserializing");
+            let value: T = from.decode(s)?;
+            Ok(Box::new(value))
+        };
+
+        let to = codec.clone();
+        let serializer = move |entry: &(dyn Any + Send + Sync)| {
+            debug!(real_type = std::any::type_name::<T>(), "serializing");
+
+            if let Some(entry) = entry.downcast_ref::<RwLock<T>>() {
+                to.encode(&read_recover(entry))
+            } else {
+                Err(serde_json::Error::custom("Type mismatch"))
+            }
+        };
+
+        let replace = move |entry: &(dyn Any + Send + Sync), value: Box<dyn Any + Send>| {
+            let (Some(entry), Ok(value)) =
+                (entry.downcast_ref::<RwLock<T>>(), value.downcast::<T>())
+            else {
+                return false;
+            };
+            *write_recover(entry) = *value;
+            true
+        };
+
+        let wrap = move |value: Box<dyn Any + Send>| {
+            let value = value.downcast::<T>().ok()?;
+            let entry: Arc<dyn Any + Send + Sync> = Arc::new(RwLock::new(*value));
+            Some(entry)
+        };
+
+        let locked = codec.clone();
+        let read_lock = read_lock_fn(move |entry| {
+            let entry = entry.downcast_ref::<RwLock<T>>()?;
+            let guard: Box<dyn LockedValue> = Box::new((read_recover(entry), locked.clone()));
+            Some(guard)
+        });
+
+        // the key stays locked from reading the current value to storing the merged one
+        let patch = move |entry: &(dyn Any + Send + Sync),
+                          key: &str,
+                          patch: &serde_json::Value,
+                          validate: &CheckFn,
+                          bump: &BumpFn| {
+            let Some(entry) = entry.downcast_ref::<RwLock<T>>() else {
+                return Err(StateError::TypeMismatch(key.to_string()));
+            };
+            let failed = |err| StateError::Deserialize(key.to_string(), err);
+            let mut guard = write_recover(entry);
+            let mut merged = codec.encode(&guard).map_err(failed)?;
+            patch::merge(&mut merged, patch);
+            let value: T = codec.decode_value(merged).map_err(failed)?;
+            validate(&value)?;
+            // re-serialize so fields T doesn't know about don't leak into emits and disk
+            let json = codec.encode(&value).map_err(failed)?;
+            *guard = value.clone();
+            let patched: Patched = (json, Box::new(value), bump());
+            Ok(patched)
+        };
+
+        Serializers {
+            type_id: TypeId::of::<T>(),
+            type_name: std::any::type_name::<T>(),
+            from_str: Box::new(deserializer),
+            to_value: Box::new(serializer),
+            replace: Box::new(replace),
+            wrap: Box::new(wrap),
+            patch: Box::new(patch),
+            read_lock: Box::new(read_lock),
+            holds: |entry| entry.is::<RwLock<T>>(),
+            #[cfg(feature = "schema")]
+            schema: || schemars::schema_for!(T).to_value(),
+        }
+    }
+}
+
+// emits held back while pause_emits is in effect, coalesced to the latest value per key.
+// pauses nest, emitting resumes once every pause has been matched by a resume
+#[derive(Default)]
+struct PausedEmits {
+    depth: usize,
+    pending: HashMap<String, serde_json::Value>,
+}
+
+// change events waiting out emit_throttle, each due at its deadline with the latest value
+#[derive(Default)]
+struct ThrottledEmits {
+    pending: HashMap<String, (std::time::Instant, serde_json::Value)>,
+    // whether a thread is currently sending them
+    running: bool,
 }
 
-type MapAny = HashMap<String, Pin<Box<dyn Any + Send + Sync>>>;
+// called with a value about to be stored for the key, downcast back to its type by the
+// closure set_validator builds
+type ValidateFn = Arc<dyn Fn(&dyn Any) -> Result<(), String> + Send + Sync>;
+
+// merges a key's value (or its lock) with a stale update, None if either is another type
+type ResolveFn = Arc<dyn Fn(&dyn Any, &dyn Any) -> Option<Box<dyn Any + Send>> + Send + Sync>;
+
+// a key's value serialized at the given version
+type CachedJson = (u128, Arc<serde_json::Value>);
+
+type MapAny = HashMap<String, Arc<dyn Any + Send + Sync>>;
 type SerializersMap = HashMap<String, Serializers>;
 
+// what the syncer does with an error it has no caller to return to, like a value from the
+// frontend that doesn't parse or an emit that fails
+#[derive(Clone, Default)]
+pub enum ErrorPolicy {
+    Panic,
+    #[default]
+    Log,
+    Callback(Arc<dyn Fn(&StateError) + Send + Sync>),
+}
+
+// how changes are sent to the frontend
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmitMode {
+    // the whole value on every change
+    #[default]
+    Full,
+    // only the changed fields, as a JSON Merge Patch on {event_prefix}{key}_patch. the first
+    // emit, and a value with a null field, send the whole value
+    Patch,
+}
+
 #[derive(Clone)]
 pub struct StateSyncerConfig {
     pub filename: String,
+    // the directory the store file goes in, relative to the app data directory unless it's
+    // absolute, e.g. a per-profile subfolder. created if it doesn't exist
+    pub directory: Option<std::path::PathBuf>,
     pub persist_keys: HashMap<String, bool>,
     pub default_persist: bool,
+    // when set, disk writes are coalesced so each key is saved at most once per interval
+    pub persist_debounce: Option<Duration>,
+    // when true, serializing and writing to disk happens on a background thread
+    pub async_persist: bool,
+    // when true, every persisted change is also appended to {filename}.wal and replayed if
+    // the store file missed it before a crash
+    pub write_ahead_log: bool,
+    // update events are named {event_prefix}{key}{event_suffix}
+    pub event_prefix: String,
+    pub event_suffix: String,
+    // when true, set and the emits a window starts from (emit, emit_all, emit_to_window)
+    // send {event_prefix}{key}_init instead of an update
+    pub init_events: bool,
+    // how the store file is encoded on disk, emits to the frontend are always JSON
+    pub format: SerdeFormat,
+    // with SerdeFormat::Json, write the store file indented (keys are always sorted) so it
+    // diffs well, e.g. when it's checked in as a test fixture
+    pub pretty: bool,
+    // when set, the store file is compressed, needs the gzip or zstd feature
+    pub compression: Option<Compression>,
+    // when set, persisted values are encrypted before they're written to the store
+    pub encryption: Option<EncryptionConfig>,
+    pub emit_mode: EmitMode,
+    pub on_error: ErrorPolicy,
+    // how many previous store files to keep as {filename}.1 up to {filename}.{backups}, the
+    // newest that loads replaces a store file that won't
+    pub backups: usize,
+    // when true, external edits to the store file are loaded back in and emitted
+    pub watch_file: bool,
+    // how many previous values to keep per key for undo, 0 keeps none
+    pub history_depth: usize,
+    // when set, change events for a key are sent at most once per interval, at the end of
+    // it and with the latest value, e.g. for state driven by pointer movement
+    pub emit_throttle: Option<Duration>,
+    // how many more times to try a failed emit, backing off from 10ms up to 1s
+    pub emit_retries: u8,
+    // when set, adding a key beyond this many evicts the least recently used key that isn't
+    // persisted
+    pub max_keys: Option<usize>,
+    // when set, a line is appended to this file for every change, see AuditLog. relative to
+    // the app data directory unless it's absolute
+    pub audit_log: Option<std::path::PathBuf>,
 }
 
 impl Default for StateSyncerConfig {
     fn default() -> Self {
         Self {
             filename: "state.json".to_owned(),
+            directory: None,
             persist_keys: HashMap::new(),
             default_persist: false,
+            persist_debounce: None,
+            async_persist: false,
+            write_ahead_log: false,
+            event_prefix: String::new(),
+            event_suffix: "_update".to_owned(),
+            init_events: false,
+            format: SerdeFormat::Json,
+            pretty: false,
+            compression: None,
+            encryption: None,
+            emit_mode: EmitMode::Full,
+            on_error: ErrorPolicy::Log,
+            backups: 0,
+            watch_file: false,
+            history_depth: 0,
+            emit_throttle: None,
+            emit_retries: 0,
+            max_keys: None,
+            audit_log: None,
         }
     }
 }
 
-#[derive(Clone)]
-pub struct StateSyncer {
-    data: Arc<Mutex<MapAny>>,
-    serializers: Arc<Mutex<SerializersMap>>,
-    app: AppHandle,
-    cfg: StateSyncerConfig,
-    disk_store: Arc<Store<tauri::Wry>>,
+impl StateSyncerConfig {
+    pub fn builder() -> StateSyncerConfigBuilder {
+        StateSyncerConfigBuilder::default()
+    }
+
+    // filename inside the configured directory, as handed to the store plugin
+    fn store_path(&self, filename: &str) -> std::path::PathBuf {
+        match &self.directory {
+            Some(directory) => directory.join(filename),
+            None => filename.into(),
+        }
+    }
 }
 
-impl StateSyncer {
-    pub fn new(cfg: StateSyncerConfig, app: AppHandle) -> Self {
-        let syncer = StateSyncer {
-            data: Default::default(),
-            serializers: Default::default(),
-            app: app.clone(),
-            cfg: cfg.clone(),
-            disk_store: app.store(cfg.filename).unwrap(),
-        };
+// StateSyncerConfigBuilder builds a StateSyncerConfig, starting from the defaults
+#[derive(Clone, Default)]
+pub struct StateSyncerConfigBuilder {
+    cfg: StateSyncerConfig,
+}
 
-        syncer
+impl StateSyncerConfigBuilder {
+    pub fn filename(mut self, filename: impl Into<String>) -> Self {
+        self.cfg.filename = filename.into();
+        self
     }
 
-    pub fn load<'a, T: ItemTrait + std::default::Default>(&self, key: &str) -> T {
-        let mut new_value: T = Default::default();
-
-        let persist = self.cfg.persist_keys.get(key).unwrap_or(&self.cfg.default_persist);
-        if !persist {
-            warn!(
-                key,
-                "load called for key not configured to persist, returning default"
-            );
-            self.set::<T>(key, new_value.clone());
-            return new_value;
-        }
+    pub fn directory(mut self, directory: impl Into<std::path::PathBuf>) -> Self {
+        self.cfg.directory = Some(directory.into());
+        self
+    }
 
-        debug!(key, "loading from disk");
-        new_value = match self.disk_store.get(key) {
-            Some(val) => match serde_json::from_value(val) {
-                Ok(res) => res,
-                Err(_) => {
-                    error!(key, "value for key did not match specified type");
-                    new_value
-                }
-            },
-            None => {
-                warn!(key, "load called for key not on disk");
-                new_value
-            }
-        };
+    // override default_persist for a single key
+    pub fn persist_key(mut self, key: impl Into<String>, persist: bool) -> Self {
+        self.cfg.persist_keys.insert(key.into(), persist);
+        self
+    }
 
-        self.set::<T>(key, new_value.clone());
+    pub fn default_persist(mut self, persist: bool) -> Self {
+        self.cfg.default_persist = persist;
+        self
+    }
 
-        new_value
+    pub fn persist_debounce(mut self, interval: Duration) -> Self {
+        self.cfg.persist_debounce = Some(interval);
+        self
     }
 
-    pub fn save<'a, T: ItemTrait>(&self, key: &str) {
-        let persist = self.cfg.persist_keys.get(key).unwrap_or(&self.cfg.default_persist);
-        if !persist {
-            error!(key, "save called for key not configured to persist, ignoring");
-            return;
-        }
-        let value = self.snapshot::<T>(key);
+    pub fn async_persist(mut self, async_persist: bool) -> Self {
+        self.cfg.async_persist = async_persist;
+        self
+    }
 
-        self.persist(key, value);
+    pub fn write_ahead_log(mut self, write_ahead_log: bool) -> Self {
+        self.cfg.write_ahead_log = write_ahead_log;
+        self
     }
 
-    fn persist<'a, T: ItemTrait>(&self, key: &str, value: T) {
-        self.disk_store.set(key, serde_json::json!(value));
+    pub fn event_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.cfg.event_prefix = prefix.into();
+        self
     }
 
-    pub fn update_typed_string<'a, T: ItemTrait>(&self, key: &str, value: &'a str, emit: bool) {
-        debug!(key, "update_typed_string");
-        let new_value: T = match serde_json::from_str(value) {
-            Ok(res) => res,
-            Err(_) => {
-                error!("failed to parse internal state");
-                return;
-            }
-        };
+    pub fn event_suffix(mut self, suffix: impl Into<String>) -> Self {
+        self.cfg.event_suffix = suffix.into();
+        self
+    }
 
-        self.update(key, new_value, emit);
+    pub fn init_events(mut self, init_events: bool) -> Self {
+        self.cfg.init_events = init_events;
+        self
     }
 
-    pub fn update<'a, T: ItemTrait>(&self, key: &str, new_value: T, emit: bool) {
-        debug!(key, "update: {}", debug_val(&new_value));
-        let key_exists: bool;
-        {
-            let guard = self.data.lock().unwrap();
-            key_exists = guard.contains_key(key);
-        }
-        if !key_exists {
-            info!("updating a key that doesn't exist yet, setting it instead");
-            self.set(key, new_value);
-            return;
-        }
+    pub fn format(mut self, format: SerdeFormat) -> Self {
+        self.cfg.format = format;
+        self
+    }
 
-        let guard = self.data.lock().unwrap();
-        let ptr = guard.get(key).unwrap();
-        let value = unsafe {
-            ptr.downcast_ref::<Mutex<T>>()
-                // SAFETY: the type of the key is the same as the type of the value
-                .unwrap_unchecked()
-        };
-        let v_ref = unsafe { &*(value as *const Mutex<T>) };
+    pub fn pretty(mut self, pretty: bool) -> Self {
+        self.cfg.pretty = pretty;
+        self
+    }
 
-        let mut v_guard = v_ref.lock().unwrap();
-        *v_guard = new_value.clone();
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.cfg.compression = Some(compression);
+        self
+    }
 
-        let persist = self.cfg.persist_keys.get(key).unwrap_or(&self.cfg.default_persist);
-        if *persist {
-            self.persist(key, new_value.clone());
-        }
+    pub fn encryption(mut self, encryption: EncryptionConfig) -> Self {
+        self.cfg.encryption = Some(encryption);
+        self
+    }
 
-        if emit {
-            let key = format!("{}_update", key);
-            debug!("emitting {}: {}", key, debug_val(&new_value));
-            self.app
-                .emit(key.as_str(), new_value.clone())
-                .expect("unable to emit state");
-        }
+    pub fn emit_mode(mut self, mode: EmitMode) -> Self {
+        self.cfg.emit_mode = mode;
+        self
     }
 
-    pub fn set<'a, T: ItemTrait>(&self, key: &str, value: T) {
-        debug!(key, "set: {}", debug_val(&value));
+    pub fn on_error(mut self, policy: ErrorPolicy) -> Self {
+        self.cfg.on_error = policy;
+        self
+    }
 
-        {
-            let mut ds_guard = self.serializers.lock().unwrap();
-            if !ds_guard.contains_key(key) {
-                debug!(key, "no serializers set for this key yet, adding it");
-                let deserializer =
-                    move |s: &str| -> Result<Box<dyn Any + Send>, serde_json::Error> {
-                        debug!(type = std::any::type_name:: <T>(), "deserializing");
-                        let value: T = serde_json::from_str(s)?;
-                        Ok(Box::new(value))
-                    };
-
-                let serializer = move |obj: &dyn Any| {
-                    debug!(real_type = std::any::type_name::<T>(), "serializing");
-
-                    if let Some(concrete) = obj.downcast_ref::<T>() {
-                        serde_json::to_string::<T>(concrete)
-                    } else {
-                        Err(serde_json::Error::custom("Type mismatch"))
-                    }
-                };
+    pub fn backups(mut self, backups: usize) -> Self {
+        self.cfg.backups = backups;
+        self
+    }
 
-                let s = Serializers {
-                    _from_str: Box::new(deserializer),
-                    _to_str: Box::new(serializer),
-                };
+    pub fn watch_file(mut self, watch: bool) -> Self {
+        self.cfg.watch_file = watch;
+        self
+    }
 
-                ds_guard.insert(key.to_string(), s);
-            }
-        }
+    pub fn history_depth(mut self, depth: usize) -> Self {
+        self.cfg.history_depth = depth;
+        self
+    }
 
-        let mut map_guard = self.data.lock().unwrap();
-        map_guard.insert(key.to_string(), Box::pin(Mutex::new(value.clone())));
-        let persist = self.cfg.persist_keys.get(key).unwrap_or(&self.cfg.default_persist);
-        if *persist {
-            self.persist(key, value.clone());
-        }
+    pub fn emit_throttle(mut self, interval: Duration) -> Self {
+        self.cfg.emit_throttle = Some(interval);
+        self
     }
 
-    // get a mutex protexted item that will emit an update event when dropped
-    pub fn get<'a, T: ItemTrait>(&'a self, key: &'a str) -> Item<'a, T> {
-        debug!(key, "get");
-        let guard = self.data.lock().unwrap();
-        let ptr = guard.get(key).unwrap();
-        let value = unsafe {
-            ptr.downcast_ref::<Mutex<T>>()
-                // SAFETY: the type of the key is the same as the type of the value
-                .unwrap_unchecked()
-        };
-        let v_ref = unsafe { &*(value as *const Mutex<T>) };
+    pub fn emit_retries(mut self, retries: u8) -> Self {
+        self.cfg.emit_retries = retries;
+        self
+    }
 
-        let persist = self.cfg.persist_keys.get(key).unwrap_or(&self.cfg.default_persist);
-        Item(
-            v_ref,
-            key,
-            &self.app,
-            persist,
-            &self.disk_store,
-        )
+    pub fn max_keys(mut self, max_keys: usize) -> Self {
+        self.cfg.max_keys = Some(max_keys);
+        self
     }
 
-    // snapshot an Item in the cache as a read-only reference of the current state
-    pub fn snapshot<'a, T: ItemTrait>(&'a self, key: &'a str) -> T {
-        debug!(key, "get");
-        let guard = self.data.lock().unwrap();
-        let ptr = guard.get(key).unwrap();
-        let value = unsafe {
-            ptr.downcast_ref::<Mutex<T>>()
-                // SAFETY: the type of the key is the same as the type of the value
-                .unwrap_unchecked()
-        };
-        let v_ref = unsafe { &*(value as *const Mutex<T>) };
-        let guard = v_ref.lock().unwrap();
+    pub fn audit_log(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.cfg.audit_log = Some(path.into());
+        self
+    }
 
-        guard.clone()
+    pub fn build(self) -> StateSyncerConfig {
+        self.cfg
     }
+}
 
-    // emit an update event for the current item's state
-    pub fn emit<'a, T: ItemTrait>(&self, name: &str) -> bool {
-        debug!(key = name, "emit");
-        let guard = self.data.lock().unwrap();
-        let ptr = guard.get(name).unwrap();
-        let value = unsafe {
-            ptr.downcast_ref::<Mutex<T>>()
-                // SAFETY: the type of the key is the same as the type of the value
-                .unwrap_unchecked()
-        };
-        let v_ref = unsafe { &*(value as *const Mutex<T>) };
-        let value: MutexGuard<'_, T> = match v_ref.lock() {
-            Ok(val) => val,
-            Err(_) => return false,
-        };
+pub struct StateSyncer<R: Runtime = Wry> {
+    // only guards which keys exist, each value has its own lock
+    data: Arc<RwLock<MapAny>>,
+    serializers: Arc<Mutex<SerializersMap>>,
+    versions: Arc<Mutex<HashMap<String, u128>>>,
+    defaults: Arc<Mutex<HashMap<String, Box<dyn Any + Send + Sync>>>>,
+    // keys set with set_ephemeral, never persisted regardless of the config
+    ephemeral: Arc<Mutex<HashSet<String>>>,
+    // keys whose values are never logged
+    sensitive: Arc<Mutex<HashSet<String>>>,
+    migrations: Arc<Mutex<Migrations>>,
+    observers: Arc<Mutex<Observers>>,
+    validators: Arc<Mutex<HashMap<String, ValidateFn>>>,
+    // what a validated key held when an Item first locked it, put back if the validator
+    // rejects what the Item leaves behind
+    rollback: Arc<Mutex<HashMap<String, Box<dyn Any + Send + Sync>>>>,
+    resolvers: Arc<Mutex<HashMap<String, ResolveFn>>>,
+    codecs: CodecMap,
+    middleware: Arc<Mutex<Vec<MiddlewareFn>>>,
+    #[cfg(feature = "tokio")]
+    updates: tokio::sync::broadcast::Sender<StateUpdate>,
+    // the last value broadcast for each key, what EmitMode::Patch diffs against
+    last_emitted: Arc<Mutex<HashMap<String, serde_json::Value>>>,
+    // the JSON of a key's value and the version it was serialized at, so a change is only
+    // serialized once
+    serialized: Arc<Mutex<HashMap<String, CachedJson>>>,
+    history: Arc<Mutex<History>>,
+    paused_emits: Arc<Mutex<PausedEmits>>,
+    throttled_emits: Arc<Mutex<ThrottledEmits>>,
+    // the latest change event for each key made while there was no window to receive it,
+    // replayed by on_run_event once one shows up, or else by the next change that's emitted
+    offline_emits: Arc<Mutex<HashMap<String, serde_json::Value>>>,
+    // when each key was last used, for evicting past max_keys
+    recency: Arc<Mutex<Recency>>,
+    // when each key set with set_with_ttl expires
+    expiries: Arc<Mutex<HashMap<String, std::time::Instant>>>,
+    // set_sync_to_disk turns persisting off and on without touching cfg
+    sync_to_disk: Arc<AtomicBool>,
+    version_counter: Arc<AtomicU64>,
+    // the sequence number of the last event sent, see VersionedValue::seq
+    emit_seq: Arc<AtomicU64>,
+    origin: Arc<str>,
+    // None for a syncer made with with_sink, which can't open a store without it
+    app: Option<AppHandle<R>>,
+    sink: Arc<dyn EventSink>,
+    cfg: StateSyncerConfig,
+    // None when running in memory only
+    disk: Arc<Mutex<Option<Disk<R>>>>,
+    // held from checking a change is still a key's latest to writing it, see persist_at
+    disk_order: Arc<Mutex<()>>,
+    audit: Option<Arc<AuditLog>>,
+    // whether a file watcher thread holds a handle, see is_last_handle
+    watched: bool,
+}
+
+// where a syncer persists to, swapped out as a whole by set_store_file
+struct Disk<R: Runtime> {
+    store: Arc<Store<R>>,
+    file: DiskFile,
+    // set when writes are made on a background thread
+    persister: Option<Arc<PersistWorker>>,
+}
 
-        let key = format!("{}_update", name);
-        debug!("emitting {}: {}", name, debug_val(&*value));
-        self.app
-            .emit(key.as_str(), value.clone())
-            .expect("unable to emit state");
-        return true;
+impl<R: Runtime> Clone for Disk<R> {
+    fn clone(&self) -> Self {
+        Disk {
+            store: self.store.clone(),
+            file: self.file.clone(),
+            persister: self.persister.clone(),
+        }
     }
 }
 
-#[macro_export]
-macro_rules! state_handlers {
-    ($($state_type:ident = $state_name:expr),* $(,)?) => {
-        #[tauri::command]
-        #[specta::specta]
-        fn emit_state(name: String, state_syncer: tauri::State<'_, tauri_svelte_synced_store::StateSyncer>) -> bool {
-            tracing::info!("emit_state: {:?}", name);
+// derived Clone would require R: Clone, which runtimes aren't
+impl<R: Runtime> Clone for StateSyncer<R> {
+    fn clone(&self) -> Self {
+        StateSyncer {
+            data: self.data.clone(),
+            serializers: self.serializers.clone(),
+            versions: self.versions.clone(),
+            defaults: self.defaults.clone(),
+            ephemeral: self.ephemeral.clone(),
+            sensitive: self.sensitive.clone(),
+            migrations: self.migrations.clone(),
+            observers: self.observers.clone(),
+            validators: self.validators.clone(),
+            rollback: self.rollback.clone(),
+            resolvers: self.resolvers.clone(),
+            codecs: self.codecs.clone(),
+            middleware: self.middleware.clone(),
+            #[cfg(feature = "tokio")]
+            updates: self.updates.clone(),
+            last_emitted: self.last_emitted.clone(),
+            serialized: self.serialized.clone(),
+            history: self.history.clone(),
+            paused_emits: self.paused_emits.clone(),
+            throttled_emits: self.throttled_emits.clone(),
+            offline_emits: self.offline_emits.clone(),
+            recency: self.recency.clone(),
+            expiries: self.expiries.clone(),
+            sync_to_disk: self.sync_to_disk.clone(),
+            version_counter: self.version_counter.clone(),
+            emit_seq: self.emit_seq.clone(),
+            origin: self.origin.clone(),
+            app: self.app.clone(),
+            sink: self.sink.clone(),
+            cfg: self.cfg.clone(),
+            disk: self.disk.clone(),
+            disk_order: self.disk_order.clone(),
+            audit: self.audit.clone(),
+            watched: self.watched,
+        }
+    }
+}
 
-            match name.as_str() {
-                $(
-                    $state_name => state_syncer.emit::<$state_type>($state_name),
-                )*
-                _ => return false,
+impl<R: Runtime> StateSyncer<R> {
+    // if the store can't be opened the syncer falls back to running in memory
+    pub fn new(cfg: StateSyncerConfig, app: AppHandle<R>) -> Self {
+        match Self::open_disk(&app, &cfg, &cfg.filename) {
+            Ok(disk) => Self::with_store(cfg, app, Some(disk)),
+            Err(err) => {
+                // a file that fails to load is left as is rather than overwritten with defaults
+                error!(
+                    filename = cfg.filename,
+                    "unable to open store, state will not be persisted: {}", err
+                );
+                Self::with_store(cfg, app, None)
             }
         }
+    }
 
-        #[tauri::command]
-        #[specta::specta]
-        fn update_state(state: tauri_svelte_synced_store::StateUpdate, state_syncer: tauri::State<'_, tauri_svelte_synced_store::StateSyncer>) -> bool {
-            tracing::info!("update_state: {}", tauri_svelte_synced_store::debug_val(&state));
-
-            match state.name.as_str() {
-                $(
-                    $state_name => {
-                        state_syncer
-                            .update_typed_string::<$state_type>($state_name, state.value.as_str(), true);
+    fn open_disk(
+        app: &AppHandle<R>,
+        cfg: &StateSyncerConfig,
+        filename: &str,
+    ) -> Result<Disk<R>, tauri_plugin_store::Error> {
+        let path = tauri_plugin_store::resolve_store_path(app, cfg.store_path(filename))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(tauri_plugin_store::Error::Io)?;
+        }
+        let store = match Self::open_store(app, cfg, filename) {
+            Err(err) if cfg.backups > 0 => {
+                warn!(filename, "unable to open store: {}", err);
+                match disk::restore_backup(&path, cfg.format.deserialize_fn()) {
+                    Ok(Some(index)) => {
+                        warn!(filename, index, "restored store from backup");
+                        Self::open_store(app, cfg, filename)?
+                    }
+                    Ok(None) => return Err(err),
+                    Err(restore_err) => {
+                        error!("failed to restore backup: {}", restore_err);
+                        return Err(err);
                     }
-                )*
-                _ => {
-                    tracing::warn!("unknown type")
                 }
             }
-            return true;
+            store => store?,
+        };
+        let file = DiskFile::new(
+            path,
+            cfg.format.serialize_fn(cfg.pretty),
+            // encrypted values were compressed before they were sealed
+            cfg.compression.filter(|_| cfg.encryption.is_none()),
+            cfg.backups,
+            cfg.write_ahead_log,
+        );
+        match file.replay(&store) {
+            Ok(0) => {}
+            Ok(replayed) => warn!(filename, replayed, "recovered unsaved changes from the log"),
+            Err(err) => error!(filename, "failed to replay write-ahead log: {}", err),
         }
-    };
-}
 
-#[macro_export]
+        let persister = if cfg.persist_debounce.is_some() || cfg.async_persist {
+            let interval = cfg.persist_debounce.unwrap_or(Duration::ZERO);
+            match PersistWorker::new(store.clone(), file.clone(), interval) {
+                Ok(worker) => Some(Arc::new(worker)),
+                Err(err) => {
+                    // still persisted, only on the caller's thread
+                    error!(
+                        filename,
+                        "unable to spawn persist thread, writing synchronously: {}", err
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        Ok(Disk {
+            store,
+            file,
+            persister,
+        })
+    }
+
+    // the store never saves itself, flush writes the file through DiskFile instead
+    fn open_store(
+        app: &AppHandle<R>,
+        cfg: &StateSyncerConfig,
+        filename: &str,
+    ) -> Result<Arc<Store<R>>, tauri_plugin_store::Error> {
+        app.store_builder(cfg.store_path(filename))
+            .serialize(cfg.format.serialize_fn(cfg.pretty))
+            .deserialize(cfg.format.deserialize_fn())
+            .disable_auto_save()
+            .build()
+    }
+
+    // a syncer that never touches disk, e.g. for tests where the store plugin isn't set up
+    pub fn new_in_memory(cfg: StateSyncerConfig, app: AppHandle<R>) -> Self {
+        Self::with_store(cfg, app, None)
+    }
+
+    // a syncer without a Tauri app that sends its events to sink, e.g. for unit tests. it has
+    // no store, so set_store_file fails
+    pub fn with_sink(cfg: StateSyncerConfig, sink: impl EventSink + 'static) -> Self {
+        Self::build(cfg, None, Arc::new(sink), None)
+    }
+
+    fn with_store(cfg: StateSyncerConfig, app: AppHandle<R>, disk: Option<Disk<R>>) -> Self {
+        let sink = Arc::new(TauriSink(app.clone()));
+        Self::build(cfg, Some(app), sink, disk)
+    }
+
+    fn build(
+        cfg: StateSyncerConfig,
+        app: Option<AppHandle<R>>,
+        sink: Arc<dyn EventSink>,
+        disk: Option<Disk<R>>,
+    ) -> Self {
+        let watched = cfg.watch_file && disk.is_some();
+        let audit = cfg.audit_log.as_ref().and_then(|path| {
+            let resolved = match &app {
+                Some(app) => tauri_plugin_store::resolve_store_path(app, path),
+                None => Ok(path.clone()),
+            };
+            match resolved {
+                Ok(path) => Some(Arc::new(AuditLog::new(path))),
+                Err(err) => {
+                    error!(
+                        "unable to resolve audit log path, changes won't be audited: {}",
+                        err
+                    );
+                    None
+                }
+            }
+        });
+
+        let syncer = StateSyncer {
+            data: Default::default(),
+            serializers: Default::default(),
+            versions: Default::default(),
+            defaults: Default::default(),
+            ephemeral: Default::default(),
+            sensitive: Default::default(),
+            migrations: Default::default(),
+            observers: Default::default(),
+            validators: Default::default(),
+            rollback: Default::default(),
+            resolvers: Default::default(),
+            codecs: Default::default(),
+            middleware: Default::default(),
+            #[cfg(feature = "tokio")]
+            updates: tokio::sync::broadcast::channel(UPDATES_CAPACITY).0,
+            last_emitted: Default::default(),
+            serialized: Default::default(),
+            history: Arc::new(Mutex::new(History::new(cfg.history_depth))),
+            paused_emits: Default::default(),
+            throttled_emits: Default::default(),
+            offline_emits: Default::default(),
+            recency: Default::default(),
+            expiries: Default::default(),
+            sync_to_disk: Arc::new(AtomicBool::new(true)),
+            version_counter: Default::default(),
+            emit_seq: Default::default(),
+            origin: new_origin_id().into(),
+            app,
+            sink,
+            cfg: cfg.clone(),
+            disk: Arc::new(Mutex::new(disk)),
+            disk_order: Default::default(),
+            audit,
+            watched,
+        };
+
+        if watched {
+            watch::spawn(syncer.clone());
+        }
+
+        syncer
+    }
+
+    // whether this is the only handle left, not counting the file watcher's
+    fn is_last_handle(&self) -> bool {
+        Arc::strong_count(&self.data) <= 1 + usize::from(self.watched)
+    }
+
+    // whether every handle but the file watcher's is gone
+    fn is_watcher_only(&self) -> bool {
+        Arc::strong_count(&self.data) <= 1
+    }
+
+    // re-read the store file after it changed on disk, then apply and emit every key whose
+    // value there differs from the one in memory. keys without a registered type are skipped
+    pub(crate) fn reload_file(&self) {
+        let Some(Disk { store, .. }) = self.disk() else {
+            return;
+        };
+        if let Err(err) = store.reload() {
+            error!("failed to reload store file: {}", err);
+            return;
+        }
+
+        let (current, _) = self.serialize_entries(|_| true);
+        let mut changed = serde_json::Map::new();
+        for (key, value) in current {
+            match self.read_disk(&key) {
+                Ok(Some(stored)) if stored != value => {
+                    changed.insert(key, stored);
+                }
+                Ok(_) => {}
+                Err(err) => self.report(&err),
+            }
+        }
+
+        if changed.is_empty() {
+            return;
+        }
+        info!(count = changed.len(), "loading keys changed on disk");
+        if let Err(err) = self.import(changed, true) {
+            self.report(&err);
+        }
+    }
+
+    // falls back to the default registered with set_default, or T::default() if there isn't one
+    pub fn load<T: ItemTrait + std::default::Default>(&self, key: &str) -> T {
+        self.load_or(key, || self.registered_default(key).unwrap_or_default())
+    }
+
+    // like load, but the fallback is only computed when there's nothing valid on disk
+    pub fn load_or<T: ItemTrait, F: FnOnce() -> T>(&self, key: &str, f: F) -> T {
+        let _span = key_span!(key, T);
+        match self.load_from_disk(key, f) {
+            Ok(value) => value,
+            Err((err, f)) => {
+                // keep the fallback in memory only, what's on disk may just need a different key
+                warn!("using fallback value without persisting it");
+                self.report(&err);
+                let new_value = f();
+                self.insert(key, new_value.clone(), false, true);
+                new_value
+            }
+        }
+    }
+
+    // like load, but returns an error instead of falling back if the value on disk can't be decrypted
+    pub fn try_load<T: ItemTrait + std::default::Default>(
+        &self,
+        key: &str,
+    ) -> Result<T, StateError> {
+        self.try_load_or(key, || self.registered_default(key).unwrap_or_default())
+    }
+
+    pub fn try_load_or<T: ItemTrait, F: FnOnce() -> T>(
+        &self,
+        key: &str,
+        f: F,
+    ) -> Result<T, StateError> {
+        let _span = key_span!(key, T);
+        self.load_from_disk(key, f).map_err(|(err, _)| err)
+    }
+
+    // hands the fallback back on error so load_or can still use it
+    fn load_from_disk<T: ItemTrait, F: FnOnce() -> T>(
+        &self,
+        key: &str,
+        f: F,
+    ) -> Result<T, (StateError, F)> {
+        if !self.should_persist(key) {
+            warn!(
+                key,
+                "load called for key not configured to persist, returning default"
+            );
+            let new_value = f();
+            self.set::<T>(key, new_value.clone());
+            return Ok(new_value);
+        }
+
+        let new_value = match self.read_stored::<T>(key) {
+            Ok(Some(value)) => {
+                info!(key, "loaded value from disk");
+                value
+            }
+            Ok(None) => {
+                info!(key, "using fallback value");
+                f()
+            }
+            Err(err) => return Err((err, f)),
+        };
+
+        self.set::<T>(key, new_value.clone());
+
+        Ok(new_value)
+    }
+
+    // the key's value on disk, None if there isn't one or it doesn't fit T
+    fn read_stored<T: ItemTrait>(&self, key: &str) -> Result<Option<T>, StateError> {
+        debug!(key, "loading from disk");
+        match self.read_disk(key)? {
+            Some(val) => match self.codec::<T>(key).decode_value(val) {
+                Ok(res) => Ok(Some(res)),
+                Err(err) => {
+                    // the fallback is about to be persisted over it
+                    self.quarantine(key);
+                    self.report(&StateError::Deserialize(key.to_string(), err));
+                    Ok(None)
+                }
+            },
+            None => {
+                warn!(key, "load called for key not on disk");
+                Ok(None)
+            }
+        }
+    }
+
+    // load every key on disk whose type is already known, from set_default or an earlier set.
+    // keys without a registered type are skipped, returns the keys that were loaded
+    pub fn load_all(&self) -> Vec<String> {
+        let Some(Disk { store, .. }) = self.disk() else {
+            return Vec::new();
+        };
+
+        let mut loaded = Vec::new();
+        for key in store.keys() {
+            let _span = key_span!(key);
+            if !self.should_persist(&key) {
+                debug!("key on disk not configured to persist, skipping");
+                continue;
+            }
+
+            let value = match self.read_disk(&key) {
+                Ok(Some(value)) => value,
+                Ok(None) => continue,
+                Err(err) => {
+                    self.report(&err);
+                    continue;
+                }
+            };
+
+            let ds_guard = lock_recover(&self.serializers);
+            let Some(serializers) = ds_guard.get(&key) else {
+                warn!("no type registered for key on disk, skipping");
+                continue;
+            };
+            let entry = (serializers.from_str)(&value.to_string());
+            let entry = entry.map(|parsed| (serializers.wrap)(parsed));
+            drop(ds_guard);
+
+            let entry = match entry {
+                Ok(Some(entry)) => entry,
+                Ok(None) => {
+                    self.report(&StateError::TypeMismatch(key.clone()));
+                    continue;
+                }
+                Err(err) => {
+                    self.quarantine(&key);
+                    self.report(&StateError::Deserialize(key.clone(), err));
+                    continue;
+                }
+            };
+            write_recover(&self.data).insert(key.clone(), entry);
+            self.bump_version(&key);
+            self.touch(&key);
+            info!("loaded value from disk");
+            loaded.push(key);
+        }
+
+        loaded
+    }
+
+    // the raw value stored for a key, decrypted if encryption is configured and migrated
+    // to the key's current schema version
+    fn read_disk(&self, key: &str) -> Result<Option<serde_json::Value>, StateError> {
+        let Some(value) = self.disk().and_then(|disk| disk.store.get(key)) else {
+            return Ok(None);
+        };
+        let decoded = self.decode(key, value);
+        if let Err(StateError::DecryptionFailed(_)) = decoded {
+            // the fallback used instead would be persisted over it on the next change
+            self.quarantine(key);
+        }
+        decoded.map(Some)
+    }
+
+    // decrypt and migrate a value as it's stored on disk
+    fn decode(&self, key: &str, value: serde_json::Value) -> Result<serde_json::Value, StateError> {
+        let value = match &self.cfg.encryption {
+            Some(encryption) => encryption
+                .open(value)
+                .ok_or_else(|| StateError::DecryptionFailed(key.to_string()))?,
+            None => value,
+        };
+
+        lock_recover(&self.migrations)
+            .upgrade(key, value)
+            .map_err(|version| StateError::MigrationRequired(key.to_string(), version))
+    }
+
+    // keep a copy of the key's value on disk that failed to load, see quarantined.
+    // it's kept as stored, so an encrypted value stays encrypted
+    fn quarantine(&self, key: &str) {
+        let Some(disk) = self.disk() else {
+            return;
+        };
+        let Some(raw) = disk.store.get(key) else {
+            return;
+        };
+        match disk.file.quarantine(key, raw) {
+            Ok(()) => warn!(key, "value on disk failed to load, quarantined it"),
+            Err(err) => error!(key, "failed to quarantine value: {}", err),
+        }
+    }
+
+    // the value a load set aside for key because it didn't fit its type or decrypt, decoded
+    // like a load would. None if nothing is quarantined for the key
+    pub fn quarantined(&self, key: &str) -> Result<Option<serde_json::Value>, StateError> {
+        let _span = key_span!(key);
+        let Some(disk) = self.disk() else {
+            return Ok(None);
+        };
+        let quarantined = disk
+            .file
+            .quarantined()
+            .map_err(|err| StateError::Persist(tauri_plugin_store::Error::Io(err)))?;
+        match quarantined.get(key) {
+            Some(raw) => self.decode(key, raw.clone()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // forget the value quarantined for key, e.g. once it's been recovered
+    pub fn clear_quarantined(&self, key: &str) -> Result<(), StateError> {
+        let _span = key_span!(key);
+        let Some(disk) = self.disk() else {
+            return Ok(());
+        };
+        disk.file
+            .release(key)
+            .map_err(|err| StateError::Persist(tauri_plugin_store::Error::Io(err)))
+    }
+
+    // register a transform that upgrades a key's value on disk from one schema version to
+    // the next, run during load before it's deserialized
+    pub fn migrate<F>(&self, key: &str, from: u32, to: u32, f: F)
+    where
+        F: Fn(serde_json::Value) -> serde_json::Value + Send + Sync + 'static,
+    {
+        let _span = key_span!(key);
+        if to <= from {
+            error!(
+                from,
+                to, "migration must move to a newer version, ignoring it"
+            );
+            return;
+        }
+        lock_recover(&self.migrations).register(key, from, to, Box::new(f));
+    }
+
+    // register the value load falls back to when nothing valid is on disk for the key
+    pub fn set_default<T: ItemTrait>(&self, key: &str, value: T) {
+        let _span = key_span!(key, T);
+        debug!("set_default: {}", self.redact(key, &value));
+        self.register_serializers::<T>(key);
+        lock_recover(&self.defaults).insert(key.to_string(), Box::new(value));
+    }
+
+    fn registered_default<T: ItemTrait>(&self, key: &str) -> Option<T> {
+        let guard = lock_recover(&self.defaults);
+        let value = guard.get(key)?;
+        match value.downcast_ref::<T>() {
+            Some(value) => Some(value.clone()),
+            None => {
+                warn!(key, "registered default has a different type, ignoring it");
+                None
+            }
+        }
+    }
+
+    pub fn save<T: ItemTrait>(&self, key: &str) {
+        if !self.should_persist(key) {
+            error!(
+                key,
+                "save called for key not configured to persist, ignoring"
+            );
+            return;
+        }
+        if let Err(err) = self.try_save::<T>(key) {
+            self.report(&err);
+        }
+    }
+
+    // like save, but returns an error if the key can't be read or serialized.
+    // keys not configured to persist are left alone
+    pub fn try_save<T: ItemTrait>(&self, key: &str) -> Result<(), StateError> {
+        let _span = key_span!(key, T);
+        if !self.should_persist(key) {
+            return Ok(());
+        }
+        let value = self.snapshot_checked::<T>(key)?;
+        let value = self
+            .codec::<T>(key)
+            .encode(&value)
+            .map_err(|err| StateError::Serialize(key.to_string(), err))?;
+
+        self.persist_value(key, value);
+        Ok(())
+    }
+
+    // write every persisted key to disk and save once, without naming their types.
+    // keys that fail to serialize are skipped and reported in the error
+    pub fn save_all(&self) -> Result<(), StateError> {
+        debug!("save_all");
+        let (values, failed) = self.serialize_entries(|key| self.should_persist(key));
+        for (key, value) in values {
+            self.persist_value(&key, value);
+        }
+
+        self.flush()?;
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(StateError::SerializeFailed(failed))
+        }
+    }
+
+    // every key serialized to JSON without naming their types, keys that fail to serialize
+    // are logged and left out
+    pub fn export(&self) -> serde_json::Map<String, serde_json::Value> {
+        debug!("export");
+        let (values, _) = self.serialize_entries(|_| true);
+        values.into_iter().collect()
+    }
+
+    // serialize the keys matching filter, along with the keys that failed
+    fn serialize_entries(
+        &self,
+        filter: impl Fn(&str) -> bool,
+    ) -> (Vec<(String, serde_json::Value)>, Vec<KeyError>) {
+        // collect the entries first so the data map isn't held while the serializers are locked
+        let entries: Vec<(String, Arc<dyn Any + Send + Sync>)> = read_recover(&self.data)
+            .iter()
+            .filter(|(key, _)| filter(key))
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect();
+
+        let ds_guard = lock_recover(&self.serializers);
+        let mut values = Vec::with_capacity(entries.len());
+        let mut failed = Vec::new();
+        for (key, entry) in entries {
+            let Some(serializers) = ds_guard.get(&key) else {
+                continue;
+            };
+            match (serializers.to_value)(entry.as_ref()) {
+                Ok(value) => values.push((key, value)),
+                Err(err) => {
+                    error!(key, "failed to serialize value: {}", err);
+                    failed.push((key, err));
+                }
+            }
+        }
+
+        (values, failed)
+    }
+
+    fn should_persist(&self, key: &str) -> bool {
+        if !self.sync_to_disk.load(Ordering::Relaxed) || lock_recover(&self.ephemeral).contains(key)
+        {
+            return false;
+        }
+        *self
+            .cfg
+            .persist_keys
+            .get(key)
+            .unwrap_or(&self.cfg.default_persist)
+    }
+
+    // the backing store, or None when running in memory
+    fn disk(&self) -> Option<Disk<R>> {
+        let disk = self.current_disk();
+        if disk.is_none() {
+            warn!("no disk store available, skipping disk operation");
+        }
+        disk
+    }
+
+    // like disk, for callers that expect to be running in memory at times
+    fn current_disk(&self) -> Option<Disk<R>> {
+        lock_recover(&self.disk).clone()
+    }
+
+    // whether writes are saved by the persist thread rather than explicit flushes
+    fn saves_in_background(&self) -> bool {
+        self.current_disk()
+            .is_some_and(|disk| disk.persister.is_some())
+    }
+
+    // write a change unless a later one or a remove got there first
+    fn persist_at(&self, key: &str, version: u128, write: impl FnOnce()) {
+        let _order = lock_recover(&self.disk_order);
+        if self.current_version(key) == Some(version) {
+            write();
+        }
+    }
+
+    // persisting only buffers the value in the store, call flush to write it to disk
+    fn persist<T: ItemTrait>(&self, key: &str, value: T) {
+        let encode = self.disk_encoder(key);
+        let codec = self.codec::<T>(key);
+        let name = key.to_string();
+        self.write_disk(
+            key,
+            Box::new(move || match codec.encode(&value) {
+                Ok(value) => encode(value),
+                Err(err) => {
+                    error!(
+                        key = name,
+                        "failed to serialize value, skipping disk write: {}", err
+                    );
+                    None
+                }
+            }),
+        );
+    }
+
+    fn persist_value(&self, key: &str, value: serde_json::Value) {
+        let encode = self.disk_encoder(key);
+        self.write_disk(key, Box::new(move || encode(value)));
+    }
+
+    fn write_disk(&self, key: &str, encode: persist::Encode) {
+        let Some(disk) = self.disk() else {
+            return;
+        };
+        // the write itself may be put off, the log needs the value now
+        let encode: persist::Encode = if disk.file.logs_writes() {
+            let value = encode();
+            if let Some(value) = &value {
+                self.log_write(&disk, key, Some(value));
+            }
+            Box::new(move || value)
+        } else {
+            encode
+        };
+        if let Some(persister) = &disk.persister {
+            persister.write(key, encode);
+        } else if let Some(value) = encode() {
+            disk.store.set(key, value);
+        }
+    }
+
+    // tags a value with the key's schema version and encrypts it if configured
+    fn disk_encoder(
+        &self,
+        key: &str,
+    ) -> impl FnOnce(serde_json::Value) -> Option<serde_json::Value> + Send + 'static {
+        let encryption = self.cfg.encryption.clone();
+        let compression = self.cfg.compression;
+        let version = lock_recover(&self.migrations).schema_version(key);
+        move |value| {
+            seal(
+                encryption.as_ref(),
+                compression,
+                migrate::tag(version, value),
+            )
+        }
+    }
+
+    fn log_write(&self, disk: &Disk<R>, key: &str, value: Option<&serde_json::Value>) {
+        let version = self.current_version(key).unwrap_or_default();
+        if let Err(err) = disk.file.log(key, value, version) {
+            error!(key, "failed to append to write-ahead log: {}", err);
+        }
+    }
+
+    fn unpersist(&self, key: &str) {
+        let Some(disk) = self.disk() else {
+            return;
+        };
+        self.log_write(&disk, key, None);
+        if let Some(persister) = &disk.persister {
+            persister.delete(key);
+        } else {
+            disk.store.delete(key);
+        }
+    }
+
+    // switch to persisting to a different file, e.g. one per user. the current file is
+    // flushed first and the new file's values replace registered keys, nothing is emitted
+    pub fn set_store_file(&self, filename: &str) -> Result<(), StateError> {
+        debug!(filename, "set_store_file");
+        self.flush()?;
+
+        let Some(app) = &self.app else {
+            return Err(StateError::Persist(tauri_plugin_store::Error::Io(
+                std::io::Error::other("a syncer made with with_sink has no store to switch"),
+            )));
+        };
+        let disk = Self::open_disk(app, &self.cfg, filename).map_err(StateError::Persist)?;
+        let previous = lock_recover(&self.disk).replace(disk);
+        if let Some(previous) = previous {
+            // let the store plugin drop its copy, the file may be opened again later
+            previous.store.close_resource();
+        }
+
+        let loaded = self.load_all();
+        info!(filename, count = loaded.len(), "switched store file");
+        Ok(())
+    }
+
+    // stop or resume writing changes to disk, e.g. for a private mode. changes made while
+    // disabled aren't written when it's turned back on
+    pub fn set_sync_to_disk(&self, enabled: bool) {
+        debug!(enabled, "set_sync_to_disk");
+        self.sync_to_disk.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn sync_to_disk(&self) -> bool {
+        self.sync_to_disk.load(Ordering::Relaxed)
+    }
+
+    // wipe everything from the store file and its backups. the values in memory are kept
+    pub fn clear_disk(&self) -> Result<(), StateError> {
+        debug!("clear_disk");
+        let Some(disk) = self.disk() else {
+            return Ok(());
+        };
+        // replaying what was logged before the clear would bring the keys back
+        disk.file
+            .clear_log()
+            .map_err(|err| StateError::Persist(tauri_plugin_store::Error::Io(err)))?;
+        if let Some(persister) = &disk.persister {
+            persister.clear();
+        } else {
+            disk.store.clear();
+        }
+        self.flush()?;
+
+        disk.file
+            .remove_backups()
+            .map_err(|err| StateError::Persist(tauri_plugin_store::Error::Io(err)))
+    }
+
+    // flush on exit and replay changes made before the first window existed, call it from
+    // the closure passed to App::run
+    pub fn on_run_event(&self, event: &tauri::RunEvent) {
+        match event {
+            tauri::RunEvent::ExitRequested { .. } | tauri::RunEvent::Exit => {
+                debug!("app exiting, flushing state");
+                if let Err(err) = self.flush() {
+                    error!("failed to flush state on exit: {}", err);
+                }
+            }
+            tauri::RunEvent::WindowEvent { .. } => self.replay_offline(),
+            _ => {}
+        }
+    }
+
+    // write everything persisted so far out to the store file
+    pub fn flush(&self) -> Result<(), StateError> {
+        debug!("flushing to disk");
+        match self.disk() {
+            Some(Disk {
+                persister: Some(persister),
+                ..
+            }) => persister.flush().map_err(StateError::Persist),
+            Some(disk) => disk.file.save(&disk.store).map_err(StateError::Persist),
+            None => Ok(()),
+        }
+    }
+
+    // errors with Deserialize if the value couldn't be parsed as T (the serde error says
+    // where), or with whatever try_update_versioned rejected it for
+    pub fn update_typed_string<T: ItemTrait>(
+        &self,
+        key: &str,
+        value: &str,
+        version: Option<u128>,
+        emit: bool,
+    ) -> Result<(), StateError> {
+        let _span = key_span!(key, T);
+        debug!("update_typed_string");
+        let value = self.run_middleware(key, value)?;
+        let new_value: T = self
+            .codec::<T>(key)
+            .decode(&value)
+            .map_err(|err| StateError::Deserialize(key.to_string(), err))?;
+
+        self.try_update_versioned(key, new_value, version, emit)
+            .map(drop)
+    }
+
+    // like update_typed_string, but parsed with the key's registered serializers, for keys
+    // only known at runtime. errors with KeyNotFound if none are registered
+    pub fn update_string(
+        &self,
+        key: &str,
+        value: &str,
+        version: Option<u128>,
+        emit: bool,
+    ) -> Result<(), StateError> {
+        let _span = key_span!(key);
+        debug!("update_string");
+        let value = self.run_middleware(key, value)?;
+        let ds_guard = lock_recover(&self.serializers);
+        let serializers = ds_guard
+            .get(key)
+            .ok_or_else(|| StateError::KeyNotFound(key.to_string()))?;
+        let parsed = (serializers.from_str)(&value)
+            .map_err(|err| StateError::Deserialize(key.to_string(), err))?;
+        self.validate_erased(key, &*parsed)?;
+
+        let json = {
+            let mut parsed = parsed;
+            let mut map_guard = write_recover(&self.data);
+            if let (Some(incoming), Some(current)) = (version, self.current_version(key))
+                && incoming < current
+            {
+                let merged = map_guard
+                    .get(key)
+                    .and_then(|entry| self.resolve_conflict(key, entry.as_ref(), &*parsed));
+                let Some(merged) = merged else {
+                    debug!(incoming, current, "stale update");
+                    return Err(StateError::Stale(key.to_string()));
+                };
+                debug!(
+                    incoming,
+                    current, "stale update, merged by the conflict resolver"
+                );
+                self.validate_erased(key, &*merged)?;
+                parsed = merged;
+            }
+
+            let entry = match map_guard.get(key) {
+                Some(entry) => {
+                    if !(serializers.replace)(entry.as_ref(), parsed) {
+                        return Err(StateError::TypeMismatch(key.to_string()));
+                    }
+                    entry.clone()
+                }
+                None => {
+                    let entry = (serializers.wrap)(parsed)
+                        .ok_or_else(|| StateError::TypeMismatch(key.to_string()))?;
+                    map_guard.insert(key.to_string(), entry.clone());
+                    entry
+                }
+            };
+            self.bump_version(key);
+            // re-serialized so fields the type doesn't know about aren't passed along
+            (serializers.to_value)(entry.as_ref())
+                .map_err(|err| StateError::Serialize(key.to_string(), err))?
+        };
+        let owned_key = key.to_string();
+        let observed = self.observed_values(&ds_guard, std::iter::once((&owned_key, &json)));
+        drop(ds_guard);
+
+        self.cache_json(key, &json);
+        self.record_change(key, || Some(json.clone()));
+        self.touch(key);
+        self.evict_over_capacity(key);
+        if self.should_persist(key) {
+            self.persist_value(key, json.clone());
+        }
+        if emit {
+            self.emit_update(key, &json);
+        }
+        for (key, value, json) in observed {
+            self.notify_change_erased(&key, &*value, || json);
+        }
+
+        Ok(())
+    }
+
+    // note: like set, a persisted update is only buffered until the next flush.
+    // returns the previous value, None if the key didn't exist or the update was rejected
+    pub fn update<T: ItemTrait>(&self, key: &str, new_value: T, emit: bool) -> Option<T> {
+        self.update_versioned(key, new_value, None, emit)
+    }
+
+    // like update, but skips the emit and disk write when the value is unchanged.
+    // returns whether the value was updated
+    pub fn update_if_changed<T: ItemTrait + PartialEq>(
+        &self,
+        key: &str,
+        new_value: T,
+        emit: bool,
+    ) -> bool {
+        if let Ok(v_ref) = self.lookup::<T>(key)
+            && *read_recover(&v_ref) == new_value
+        {
+            debug!(key, "value unchanged, skipping update");
+            return false;
+        }
+
+        match self.try_update(key, new_value, emit) {
+            Ok(_) => true,
+            Err(err) => {
+                self.report(&err);
+                false
+            }
+        }
+    }
+
+    // like update, but the value is rejected (returning None) if version is older than the
+    // key's current version, e.g. when the frontend echoes back state it had before a change
+    pub fn update_versioned<T: ItemTrait>(
+        &self,
+        key: &str,
+        new_value: T,
+        version: Option<u128>,
+        emit: bool,
+    ) -> Option<T> {
+        match self.try_update_versioned(key, new_value, version, emit) {
+            Ok(previous) => previous,
+            Err(err) => {
+                self.report(&err);
+                None
+            }
+        }
+    }
+
+    // like update, but returns an error instead of logging it. Ok(None) if the key didn't
+    // exist yet
+    pub fn try_update<T: ItemTrait>(
+        &self,
+        key: &str,
+        new_value: T,
+        emit: bool,
+    ) -> Result<Option<T>, StateError> {
+        self.try_update_versioned(key, new_value, None, emit)
+    }
+
+    // like update_versioned, but returns why the update was rejected: TypeMismatch, or Stale
+    // if version is older than the key's and there's no conflict resolver
+    pub fn try_update_versioned<T: ItemTrait>(
+        &self,
+        key: &str,
+        new_value: T,
+        version: Option<u128>,
+        emit: bool,
+    ) -> Result<Option<T>, StateError> {
+        self.update_inner(key, new_value, version, emit)
+            .map(|(previous, _)| previous)
+    }
+
+    // the update itself, also handing back the json it stored so callers that emit
+    // somewhere else don't serialize the value again. None when the key was inserted
+    fn update_inner<T: ItemTrait>(
+        &self,
+        key: &str,
+        mut new_value: T,
+        version: Option<u128>,
+        emit: bool,
+    ) -> Result<(Option<T>, Option<Arc<serde_json::Value>>), StateError> {
+        let _span = key_span!(key, T);
+        debug!("update: {}", self.redact(key, &new_value));
+        self.validate(key, &new_value)?;
+        // an expired key is updated like one that doesn't exist
+        self.expire_if_due(key);
+        // hold the map for the whole update so a concurrent remove can't slip in between
+        // finding the key and writing to it
+        let guard = read_recover(&self.data);
+        let Some(ptr) = guard.get(key) else {
+            drop(guard);
+            info!("updating a key that doesn't exist yet, setting it instead");
+            return Ok((self.insert(key, new_value, true, emit), None));
+        };
+        // the key may have been set with a different type, downcast checks the TypeId
+        let Some(v_ref) = ptr.downcast_ref::<RwLock<T>>() else {
+            return Err(StateError::TypeMismatch(key.to_string()));
+        };
+
+        // checking the version, storing the value and bumping the version all happen under
+        // the key's lock, so of two updates made against the same version only one gets in
+        let mut v_guard = write_recover(v_ref);
+        if let (Some(incoming), Some(current)) = (version, self.current_version(key))
+            && incoming < current
+        {
+            let merged = self
+                .resolve_conflict(key, &*v_guard, &new_value)
+                .and_then(|merged| merged.downcast::<T>().ok());
+            let Some(merged) = merged else {
+                debug!(incoming, current, "stale update");
+                return Err(StateError::Stale(key.to_string()));
+            };
+            debug!(
+                incoming,
+                current, "stale update, merged by the conflict resolver"
+            );
+            new_value = *merged;
+            self.validate(key, &new_value)?;
+        }
+        // serialized once for history, disk and the emit, and shared with the cache
+        let json = Arc::new(
+            self.codec::<T>(key)
+                .encode(&new_value)
+                .map_err(|err| StateError::Serialize(key.to_string(), err))?,
+        );
+
+        // only copied when on_change callbacks need it after it's been stored
+        let observed = self.notifies(key).then(|| new_value.clone());
+        let previous = std::mem::replace(&mut *v_guard, new_value);
+        let version = self.bump_version(key);
+        drop(v_guard);
+        self.share_json(key, json.clone());
+        // the write-ahead log syncs to disk, the emit may sleep between retries and the audit
+        // log writes to its file, none of which should keep the other keys waiting
+        drop(guard);
+
+        if self.should_persist(key) {
+            // the disk copy gets tagged and maybe encrypted, so it needs its own
+            self.persist_at(key, version, || self.persist_value(key, (*json).clone()));
+        }
+        self.record_change(key, || Some((*json).clone()));
+        if emit {
+            self.emit_update(key, &*json);
+        }
+
+        if let Some(new_value) = observed {
+            self.notify_change_erased(key, &new_value, || json.to_string());
+        }
+        Ok((Some(previous), Some(json)))
+    }
+
+    // mutate a key in place, then emit and persist once the closure returns.
+    // returns a copy of the new value
+    pub fn update_with<T: ItemTrait, F: FnOnce(&mut T)>(
+        &self,
+        key: &str,
+        f: F,
+    ) -> Result<T, StateError> {
+        let _span = key_span!(key, T);
+        debug!("update_with");
+        self.modify(key, |value: &mut T| {
+            f(value);
+            (value.clone(), true)
+        })
+    }
+
+    // append to a Vec-valued key under its lock, emitting and persisting once
+    pub fn vec_push<T: ItemTrait>(&self, key: &str, item: T) -> Result<(), StateError> {
+        let _span = key_span!(key, Vec<T>);
+        debug!("vec_push");
+        self.modify(key, |items: &mut Vec<T>| {
+            items.push(item);
+            ((), true)
+        })
+    }
+
+    // remove every element of a Vec-valued key matching pred, returning how many were
+    // removed. nothing is emitted if none matched
+    pub fn vec_remove_where<T: ItemTrait, F: FnMut(&T) -> bool>(
+        &self,
+        key: &str,
+        mut pred: F,
+    ) -> Result<usize, StateError> {
+        let _span = key_span!(key, Vec<T>);
+        debug!("vec_remove_where");
+        self.modify(key, |items: &mut Vec<T>| {
+            let before = items.len();
+            items.retain(|item| !pred(item));
+            let removed = before - items.len();
+            (removed, removed > 0)
+        })
+    }
+
+    // insert into a HashMap-valued key under its lock, returning the value it replaced
+    pub fn map_insert<K, V>(&self, key: &str, k: K, v: V) -> Result<Option<V>, StateError>
+    where
+        K: ItemTrait + Eq + std::hash::Hash,
+        V: ItemTrait,
+    {
+        let _span = key_span!(key, HashMap<K, V>);
+        debug!("map_insert");
+        self.modify(key, |map: &mut HashMap<K, V>| (map.insert(k, v), true))
+    }
+
+    // remove an entry from a HashMap-valued key, nothing is emitted if it wasn't there
+    pub fn map_remove<K, V>(&self, key: &str, k: &K) -> Result<Option<V>, StateError>
+    where
+        K: ItemTrait + Eq + std::hash::Hash,
+        V: ItemTrait,
+    {
+        let _span = key_span!(key, HashMap<K, V>);
+        debug!("map_remove");
+        self.modify(key, |map: &mut HashMap<K, V>| {
+            let removed = map.remove(k);
+            let changed = removed.is_some();
+            (removed, changed)
+        })
+    }
+
+    // mutate a key in place under its lock, then bump, persist, emit and notify if f says it
+    // changed. with a validator f works on a copy, stored once it's accepted
+    fn modify<T: ItemTrait, U, F: FnOnce(&mut T) -> (U, bool)>(
+        &self,
+        key: &str,
+        f: F,
+    ) -> Result<U, StateError> {
+        let v_ref = self.lookup::<T>(key)?;
+        let (result, json, value) = {
+            let mut v_guard = write_recover(&v_ref);
+            let (result, changed) = if self.has_validator(key) {
+                let mut draft = v_guard.clone();
+                let (result, changed) = f(&mut draft);
+                if changed {
+                    self.validate(key, &draft)?;
+                    *v_guard = draft;
+                }
+                (result, changed)
+            } else {
+                f(&mut v_guard)
+            };
+            if !changed {
+                return Ok(result);
+            }
+            let value = self.notifies(key).then(|| v_guard.clone());
+            (result, self.codec::<T>(key).encode(&v_guard), value)
+        };
+        self.changed_json(key, json, value.as_ref());
+
+        Ok(result)
+    }
+
+    // apply an RFC 7386 JSON Merge Patch to a key, leaving it untouched if the result doesn't
+    // deserialize into the key's type
+    pub fn patch(&self, key: &str, patch: serde_json::Value, emit: bool) -> Result<(), StateError> {
+        let _span = key_span!(key);
+        debug!("patch: {}", self.redact(key, &patch));
+        // an expired key can't be patched back
+        self.expire_if_due(key);
+        let (merged, value, version) = {
+            // the map is held like in update_inner, so a remove can't slip in
+            let ds_guard = lock_recover(&self.serializers);
+            let map_guard = read_recover(&self.data);
+            let (Some(serializers), Some(entry)) = (ds_guard.get(key), map_guard.get(key)) else {
+                return Err(StateError::KeyNotFound(key.to_string()));
+            };
+            (serializers.patch)(
+                entry.as_ref(),
+                key,
+                &patch,
+                &|value| self.validate_erased(key, value),
+                &|| self.bump_version(key),
+            )?
+        };
+        self.touch(key);
+        self.cache_json(key, &merged);
+        self.record_change(key, || Some(merged.clone()));
+
+        if self.should_persist(key) {
+            self.persist_at(key, version, || self.persist_value(key, merged.clone()));
+        }
+        if emit {
+            self.emit_update(key, &merged);
+        }
+        self.notify_change_erased(key, &*value, || merged.to_string());
+
+        Ok(())
+    }
+
+    // add to a counter under the key's lock, returning the new value
+    pub fn fetch_add<T: Counter>(&self, key: &str, delta: T) -> Result<T, StateError> {
+        self.update_with(key, |value: &mut T| *value = value.add(delta))
+    }
+
+    // subtract from a counter under the key's lock, returning the new value
+    pub fn fetch_sub<T: Counter>(&self, key: &str, delta: T) -> Result<T, StateError> {
+        self.update_with(key, |value: &mut T| *value = value.sub(delta))
+    }
+
+    // swap in new only if the key currently equals expected, comparing and writing under one
+    // lock so nothing can change the key in between. returns whether the swap happened
+    pub fn compare_and_swap<T: ItemTrait + PartialEq>(
+        &self,
+        key: &str,
+        expected: &T,
+        new: T,
+    ) -> bool {
+        let _span = key_span!(key, T);
+        debug!("compare_and_swap");
+        if let Err(err) = self.validate(key, &new) {
+            self.report(&err);
+            return false;
+        }
+        let v_ref = match self.lookup::<T>(key) {
+            Ok(v_ref) => v_ref,
+            Err(err) => {
+                self.report(&err);
+                return false;
+            }
+        };
+
+        {
+            let mut v_guard = write_recover(&v_ref);
+            if *v_guard != *expected {
+                debug!("value didn't match expected, not swapping");
+                return false;
+            }
+            *v_guard = new.clone();
+        }
+        self.changed(key, &new);
+
+        true
+    }
+
+    // keep a key's new value for undo and the audit log, computing it only if either wants it
+    fn record_change(&self, key: &str, value: impl FnOnce() -> Option<serde_json::Value>) {
+        if self.cfg.history_depth == 0 && self.audit.is_none() {
+            return;
+        }
+        let Some(value) = value() else {
+            return;
+        };
+        self.audit(key, Some(&value));
+        if self.cfg.history_depth > 0 {
+            lock_recover(&self.history).record(key, value);
+        }
+    }
+
+    fn audit(&self, key: &str, value: Option<&serde_json::Value>) {
+        let Some(audit) = &self.audit else {
+            return;
+        };
+        if let Err(err) = audit.record(key, value, self.is_sensitive(key)) {
+            error!(key, "failed to write to audit log: {}", err);
+        }
+    }
+
+    // step a key back to its previous value, storing, persisting and emitting it like an
+    // update would. returns the restored value, None if there's nothing to undo
+    pub fn undo<T: ItemTrait>(&self, key: &str) -> Result<Option<T>, StateError> {
+        let _span = key_span!(key, T);
+        debug!("undo");
+        self.step_history(key, History::undo, History::redo)
+    }
+
+    // reapply the value last undone, None if nothing was undone since the key last changed
+    pub fn redo<T: ItemTrait>(&self, key: &str) -> Result<Option<T>, StateError> {
+        let _span = key_span!(key, T);
+        debug!("redo");
+        self.step_history(key, History::redo, History::undo)
+    }
+
+    fn step_history<T: ItemTrait>(
+        &self,
+        key: &str,
+        step: fn(&mut History, &str) -> Option<serde_json::Value>,
+        step_back: fn(&mut History, &str) -> Option<serde_json::Value>,
+    ) -> Result<Option<T>, StateError> {
+        self.lookup::<T>(key)?;
+        let Some(state) = step(&mut lock_recover(&self.history), key) else {
+            return Ok(None);
+        };
+        let value: T = match self.codec::<T>(key).decode_value(state) {
+            Ok(value) => value,
+            Err(err) => {
+                // the key held another type back then, stay where we were
+                step_back(&mut lock_recover(&self.history), key);
+                return Err(StateError::Deserialize(key.to_string(), err));
+            }
+        };
+
+        // recording the restored value leaves the history where the step put it
+        self.try_update(key, value.clone(), true)?;
+        Ok(Some(value))
+    }
+
+    // bump, persist, emit and notify for a key whose stored value was just changed in place
+    fn changed<T: ItemTrait>(&self, key: &str, new_value: &T) {
+        self.changed_json(key, self.codec::<T>(key).encode(new_value), Some(new_value));
+    }
+
+    // like changed, with the value already serialized. new_value is only needed by on_change
+    // callbacks, None when nothing watches the key
+    fn changed_json<T: Any>(
+        &self,
+        key: &str,
+        json: Result<serde_json::Value, serde_json::Error>,
+        new_value: Option<&T>,
+    ) {
+        self.bump_version(key);
+        let json = match json {
+            Ok(json) => json,
+            Err(err) => {
+                self.report(&StateError::Serialize(key.to_string(), err));
+                return;
+            }
+        };
+        self.cache_json(key, &json);
+        self.record_change(key, || Some(json.clone()));
+
+        if self.should_persist(key) {
+            self.persist_value(key, json.clone());
+        }
+        self.emit_update(key, &json);
+        if let Some(new_value) = new_value {
+            self.notify_change_erased(key, new_value, || json.to_string());
+        }
+    }
+
+    // remember the JSON just serialized for a key's current version, for emits to reuse
+    fn cache_json(&self, key: &str, json: &serde_json::Value) {
+        self.share_json(key, Arc::new(json.clone()));
+    }
+
+    // cache_json without the copy, for JSON the caller holds on to as well
+    fn share_json(&self, key: &str, json: Arc<serde_json::Value>) {
+        if let Some(version) = self.current_version(key) {
+            lock_recover(&self.serialized).insert(key.to_string(), (version, json));
+        }
+    }
+
+    // the key's cached JSON, as long as the key hasn't changed since it was serialized
+    fn cached_json(&self, key: &str) -> Option<Arc<serde_json::Value>> {
+        let version = self.current_version(key)?;
+        match lock_recover(&self.serialized).get(key) {
+            Some((cached, json)) if *cached == version => Some(json.clone()),
+            _ => None,
+        }
+    }
+
+    fn forget_json(&self, key: &str) {
+        lock_recover(&self.serialized).remove(key);
+    }
+
+    // apply several registered keys at once, emitting one {event_prefix}batch_update. nothing
+    // is applied if any value doesn't fit
+    pub fn update_batch(
+        &self,
+        updates: Vec<(String, serde_json::Value)>,
+        emit: bool,
+    ) -> Result<(), StateError> {
+        debug!(count = updates.len(), "update_batch");
+        let ds_guard = lock_recover(&self.serializers);
+
+        let mut parsed = Vec::with_capacity(updates.len());
+        for (key, value) in &updates {
+            let serializers = ds_guard
+                .get(key)
+                .ok_or_else(|| StateError::KeyNotFound(key.clone()))?;
+            let new_value = (serializers.from_str)(&value.to_string())
+                .map_err(|err| StateError::Deserialize(key.clone(), err))?;
+            self.validate_erased(key, new_value.as_ref())?;
+            parsed.push(new_value);
+        }
+
+        let mut versions = Vec::with_capacity(updates.len());
+        {
+            let map_guard = write_recover(&self.data);
+            // check every key before replacing any
+            for (key, _) in &updates {
+                let entry = map_guard
+                    .get(key)
+                    .ok_or_else(|| StateError::KeyNotFound(key.clone()))?;
+                if !(ds_guard[key].holds)(entry.as_ref()) {
+                    return Err(StateError::TypeMismatch(key.clone()));
+                }
+            }
+            for ((key, _), new_value) in updates.iter().zip(parsed) {
+                (ds_guard[key].replace)(map_guard[key].as_ref(), new_value);
+                versions.push(self.bump_version(key));
+            }
+        }
+        let observed =
+            self.observed_values(&ds_guard, updates.iter().map(|(key, value)| (key, value)));
+        drop(ds_guard);
+        for (key, value, json) in observed {
+            self.notify_change_erased(&key, &*value, || json);
+        }
+
+        let mut persisted = false;
+        for (key, value) in &updates {
+            self.touch(key);
+            self.evict_over_capacity(key);
+            self.cache_json(key, value);
+            self.record_change(key, || Some(value.clone()));
+            if self.should_persist(key) {
+                self.persist_value(key, value.clone());
+                persisted = true;
+            }
+        }
+        // the persist thread saves on its own, otherwise write the whole batch in one go
+        if persisted && !self.saves_in_background() {
+            self.flush()?;
+        }
+
+        if emit && self.emits_paused() {
+            // held back per key like any other change, there's no batch to resume with
+            for (key, value) in &updates {
+                self.emit_update(key, value);
+            }
+        } else if emit {
+            let payload: HashMap<String, VersionedValue<serde_json::Value>> = updates
+                .into_iter()
+                .zip(versions)
+                .map(|((key, value), version)| (key, self.versioned(version, value)))
+                .collect();
+            let name = format!("{}batch_update", self.cfg.event_prefix);
+            if payload.keys().any(|key| self.is_sensitive(key)) {
+                debug!("emitting {}: <redacted>", name);
+            } else {
+                debug!("emitting {}: {}", name, debug_val(&payload));
+            }
+            self.emit_or_log(&name, payload);
+        }
+
+        Ok(())
+    }
+
+    // the counterpart to export. every key needs a registered type and every value must fit
+    // it, or nothing is imported
+    pub fn import(
+        &self,
+        map: serde_json::Map<String, serde_json::Value>,
+        emit: bool,
+    ) -> Result<(), StateError> {
+        debug!(count = map.len(), "import");
+        let ds_guard = lock_recover(&self.serializers);
+
+        let mut parsed = Vec::with_capacity(map.len());
+        for (key, value) in &map {
+            let serializers = ds_guard
+                .get(key)
+                .ok_or_else(|| StateError::KeyNotFound(key.clone()))?;
+            let new_value = (serializers.from_str)(&value.to_string())
+                .map_err(|err| StateError::Deserialize(key.clone(), err))?;
+            self.validate_erased(key, new_value.as_ref())?;
+            parsed.push(new_value);
+        }
+
+        {
+            let mut map_guard = write_recover(&self.data);
+            // check every key before replacing any
+            for key in map.keys() {
+                if let Some(entry) = map_guard.get(key)
+                    && !(ds_guard[key].holds)(entry.as_ref())
+                {
+                    return Err(StateError::TypeMismatch(key.clone()));
+                }
+            }
+            for ((key, _), new_value) in map.iter().zip(parsed) {
+                let serializers = &ds_guard[key];
+                // swap the value in place so any Item still holding the key sees it
+                match map_guard.get(key) {
+                    Some(entry) => {
+                        (serializers.replace)(entry.as_ref(), new_value);
+                    }
+                    None => {
+                        if let Some(entry) = (serializers.wrap)(new_value) {
+                            map_guard.insert(key.clone(), entry);
+                        }
+                    }
+                }
+                self.bump_version(key);
+            }
+        }
+        let observed = self.observed_values(&ds_guard, map.iter());
+        drop(ds_guard);
+        for (key, value, json) in observed {
+            self.notify_change_erased(&key, &*value, || json);
+        }
+
+        let mut persisted = false;
+        for (key, value) in map {
+            self.touch(&key);
+            self.evict_over_capacity(&key);
+            self.cache_json(&key, &value);
+            self.record_change(&key, || Some(value.clone()));
+            if self.should_persist(&key) {
+                self.persist_value(&key, value.clone());
+                persisted = true;
+            }
+            if emit {
+                self.emit_update(&key, &value);
+            }
+        }
+        if persisted && !self.saves_in_background() {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    // stage changes to several keys and apply them together. nothing is applied if f fails
+    // or any staged change doesn't fit
+    pub fn transaction<F>(&self, f: F) -> Result<(), StateError>
+    where
+        F: FnOnce(&mut Txn) -> Result<(), StateError>,
+    {
+        let mut txn = Txn::new(self.codecs.clone());
+        f(&mut txn)?;
+        if let Some(err) = txn.error {
+            return Err(err);
+        }
+        // one rejected value fails the whole transaction before anything is stored
+        for staged in &txn.staged {
+            self.validate_erased(&staged.key, staged.value.as_ref())?;
+        }
+        debug!(count = txn.staged.len(), "committing transaction");
+
+        let mut ds_guard = lock_recover(&self.serializers);
+        let mut map_guard = write_recover(&self.data);
+        for staged in txn.staged.iter().filter(|staged| staged.must_exist) {
+            let current = ds_guard
+                .get(&staged.key)
+                .filter(|_| map_guard.contains_key(&staged.key))
+                .ok_or_else(|| StateError::KeyNotFound(staged.key.clone()))?;
+            if current.type_id != staged.serializers.type_id {
+                return Err(StateError::TypeMismatch(staged.key.clone()));
+            }
+        }
+
+        let mut changed = Vec::with_capacity(txn.staged.len());
+        for Staged {
+            key,
+            value,
+            json,
+            serializers,
+            ..
+        } in txn.staged
+        {
+            let same_type = ds_guard
+                .get(&key)
+                .is_some_and(|current| current.type_id == serializers.type_id);
+            match map_guard.get(&key) {
+                // swap the value in place so any Item still holding the key sees it
+                Some(entry) if same_type => {
+                    (serializers.replace)(entry.as_ref(), value);
+                }
+                _ => {
+                    if let Some(entry) = (serializers.wrap)(value) {
+                        map_guard.insert(key.clone(), entry);
+                    }
+                    ds_guard.insert(key.clone(), serializers);
+                }
+            }
+            self.bump_version(&key);
+            changed.push((key, json));
+        }
+        let observed =
+            self.observed_values(&ds_guard, changed.iter().map(|(key, json)| (key, json)));
+        drop(map_guard);
+        drop(ds_guard);
+
+        let mut persisted = false;
+        for (key, json) in &changed {
+            self.touch(key);
+            self.evict_over_capacity(key);
+            self.cache_json(key, json);
+            self.record_change(key, || Some(json.clone()));
+            if self.should_persist(key) {
+                self.persist_value(key, json.clone());
+                persisted = true;
+            }
+        }
+        if persisted && !self.saves_in_background() {
+            self.flush()?;
+        }
+
+        for (key, json) in &changed {
+            self.emit_update(key, json);
+        }
+        for (key, value, json) in observed {
+            self.notify_change_erased(&key, &*value, || json);
+        }
+
+        Ok(())
+    }
+
+    // note: a persisted set is only buffered in the store, call flush to write it to disk.
+    // returns the previous value, None if there wasn't one of type T
+    pub fn set<T: ItemTrait>(&self, key: &str, value: T) -> Option<T> {
+        match self.try_set(key, value) {
+            Ok(previous) => previous,
+            Err(err) => {
+                self.report(&err);
+                None
+            }
+        }
+    }
+
+    // like set, but returns Invalid if the key's validator rejects the value
+    pub fn try_set<T: ItemTrait>(&self, key: &str, value: T) -> Result<Option<T>, StateError> {
+        let _span = key_span!(key, T);
+        debug!("set: {}", self.redact(key, &value));
+        self.validate(key, &value)?;
+        Ok(self.insert(key, value, true, true))
+    }
+
+    // store a value and return the one it replaced, emitting _init with init_events
+    fn insert<T: ItemTrait>(&self, key: &str, value: T, persist: bool, emit: bool) -> Option<T> {
+        self.register_serializers::<T>(key);
+        // a plain set replaces a value set with a ttl, and the ttl with it
+        let expired = lock_recover(&self.expiries)
+            .remove(key)
+            .is_some_and(|deadline| deadline <= std::time::Instant::now());
+
+        let (previous, version) = {
+            let mut map_guard = write_recover(&self.data);
+            let previous = map_guard.insert(key.to_string(), Arc::new(RwLock::new(value.clone())));
+            (previous, self.bump_version(key))
+        };
+        self.inserted(key, &value, version, persist, emit);
+
+        // an Item still holding the old value keeps it alive, leaving only a copy to return
+        let previous = previous
+            .filter(|_| !expired)?
+            .downcast::<RwLock<T>>()
+            .ok()?;
+        Some(match Arc::try_unwrap(previous) {
+            Ok(lock) => lock.into_inner().unwrap_or_else(PoisonError::into_inner),
+            Err(shared) => read_recover(&shared).clone(),
+        })
+    }
+
+    // insert unless the key is already in memory, returns whether it was stored
+    fn insert_if_absent<T: ItemTrait>(&self, key: &str, value: T, persist: bool) -> bool {
+        let version = {
+            let mut ds_guard = lock_recover(&self.serializers);
+            let mut map_guard = write_recover(&self.data);
+            let Entry::Vacant(slot) = map_guard.entry(key.to_string()) else {
+                return false;
+            };
+            slot.insert(Arc::new(RwLock::new(value.clone())));
+            // a remove drops the serializers after the value, they may still be there
+            if ds_guard
+                .get(key)
+                .is_none_or(|existing| existing.type_id != TypeId::of::<T>())
+            {
+                ds_guard.insert(
+                    key.to_string(),
+                    Serializers::with_codec(self.codec::<T>(key)),
+                );
+            }
+            self.bump_version(key)
+        };
+        self.inserted(key, &value, version, persist, true);
+        true
+    }
+
+    // the rest of an insert, once the map is unlocked
+    fn inserted<T: ItemTrait>(
+        &self,
+        key: &str,
+        value: &T,
+        version: u128,
+        persist: bool,
+        emit: bool,
+    ) {
+        if persist && self.should_persist(key) {
+            self.persist_at(key, version, || self.persist(key, value.clone()));
+        }
+        self.record_change(key, || self.codec::<T>(key).encode(value).ok());
+        self.touch(key);
+        self.evict_over_capacity(key);
+
+        if emit
+            && self.cfg.init_events
+            && let Err(err) = self.emit_current_to(None, key, value)
+        {
+            self.report(&err);
+        }
+        self.notify_change(key, value);
+    }
+
+    // like set, but the key expires after ttl and is removed the next time it's read or
+    // sweep_expired runs. a plain set clears the ttl, which isn't persisted
+    pub fn set_with_ttl<T: ItemTrait>(
+        &self,
+        key: &str,
+        value: T,
+        ttl: Duration,
+    ) -> Result<(), StateError> {
+        let _span = key_span!(key, T);
+        debug!(?ttl, "set_with_ttl: {}", self.redact(key, &value));
+        self.validate(key, &value)?;
+        self.insert(key, value, true, true);
+        lock_recover(&self.expiries).insert(key.to_string(), std::time::Instant::now() + ttl);
+        Ok(())
+    }
+
+    // remove every key whose ttl is up, returning them. call it periodically if expired keys
+    // should go even when nothing reads them
+    pub fn sweep_expired(&self) -> Vec<String> {
+        let now = std::time::Instant::now();
+        let expired: Vec<String> = lock_recover(&self.expiries)
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter(|key| self.expire_if_due(key))
+            .collect()
+    }
+
+    // note a key as just used, for max_keys
+    fn touch(&self, key: &str) {
+        if self.cfg.max_keys.is_some() {
+            lock_recover(&self.recency).touch(key);
+        }
+    }
+
+    // evict least recently used keys until there are at most max_keys again, never the
+    // persisted ones or the key that was just added
+    fn evict_over_capacity(&self, added: &str) {
+        let Some(max_keys) = self.cfg.max_keys else {
+            return;
+        };
+        while read_recover(&self.data).len() > max_keys {
+            let evict = lock_recover(&self.recency)
+                .least_recent(|key| key != added && !self.should_persist(key));
+            let Some(evict) = evict else {
+                warn!(
+                    max_keys,
+                    "over max_keys but every key is persisted, not evicting"
+                );
+                return;
+            };
+            info!(key = evict, max_keys, "evicting least recently used key");
+            if self.try_remove(&evict).is_err() {
+                // already gone, just stop tracking it
+                lock_recover(&self.recency).forget(&evict);
+            }
+        }
+    }
+
+    // remove the key if its ttl is up, returning whether it was removed
+    fn expire_if_due(&self, key: &str) -> bool {
+        {
+            let mut expiries = lock_recover(&self.expiries);
+            match expiries.get(key) {
+                Some(deadline) if *deadline <= std::time::Instant::now() => {
+                    expiries.remove(key);
+                }
+                _ => return false,
+            }
+        }
+        debug!(key, "key expired, removing it");
+        self.try_remove(key).is_ok()
+    }
+
+    // like set, but the key is never written to disk even if the config would persist it.
+    // it still emits like any other key, until it's removed
+    pub fn set_ephemeral<T: ItemTrait>(&self, key: &str, value: T) {
+        let _span = key_span!(key, T);
+        debug!("set_ephemeral: {}", self.redact(key, &value));
+        if self.should_persist(key) {
+            // don't leave a stale durable copy around to be loaded later
+            self.unpersist(key);
+        }
+        lock_recover(&self.ephemeral).insert(key.to_string());
+
+        self.insert(key, value, false, true);
+    }
+
+    // check every value set or updated for the key, e.g. to keep a volume within 0-100. a
+    // rejection is StateError::Invalid, f mustn't call back into the syncer
+    pub fn set_validator<T, F>(&self, key: &str, f: F)
+    where
+        T: ItemTrait,
+        F: Fn(&T) -> Result<(), String> + Send + Sync + 'static,
+    {
+        let _span = key_span!(key, T);
+        let owned_key = key.to_string();
+        let validate: ValidateFn =
+            Arc::new(move |value: &dyn Any| match value.downcast_ref::<T>() {
+                Some(value) => f(value),
+                None => {
+                    warn!(
+                        key = owned_key,
+                        "key set to a different type than its validator expects, not validating"
+                    );
+                    Ok(())
+                }
+            });
+        lock_recover(&self.validators).insert(key.to_string(), validate);
+    }
+
+    // replace how a key's values are serialized, for events and the store file alike, e.g.
+    // to send a timestamp as epoch millis. from's error is StateError::Deserialize
+    pub fn set_serializer<T, F, G, E>(&self, key: &str, to: F, from: G)
+    where
+        T: ItemTrait,
+        F: Fn(&T) -> String + Send + Sync + 'static,
+        G: Fn(&str) -> Result<T, E> + Send + Sync + 'static,
+        E: std::fmt::Display,
+    {
+        let _span = key_span!(key, T);
+        let codec = Codec::custom(to, from);
+        lock_recover(&self.codecs).insert(key.to_string(), Arc::new(codec.clone()));
+
+        // a key keeps its serializers until it changes type, swap them for ones using the codec
+        let mut ds_guard = lock_recover(&self.serializers);
+        if ds_guard
+            .get(key)
+            .is_some_and(|serializers| serializers.type_id == TypeId::of::<T>())
+        {
+            ds_guard.insert(key.to_string(), Serializers::with_codec(codec));
+        }
+        drop(ds_guard);
+        // cached with the old serialization
+        self.forget_json(key);
+    }
+
+    // merge an update made against an older version of the key with its current value
+    // instead of rejecting it as Stale. f mustn't call back into the syncer
+    pub fn set_conflict_resolver<T, F>(&self, key: &str, f: F)
+    where
+        T: ItemTrait,
+        F: Fn(&T, &T) -> T + Send + Sync + 'static,
+    {
+        let _span = key_span!(key, T);
+        let owned_key = key.to_string();
+        let resolve: ResolveFn = Arc::new(move |current: &dyn Any, incoming: &dyn Any| {
+            let incoming = incoming.downcast_ref::<T>();
+            if let (Some(current), Some(incoming)) = (current.downcast_ref::<T>(), incoming) {
+                return Some(Box::new(f(current, incoming)));
+            }
+            match (current.downcast_ref::<RwLock<T>>(), incoming) {
+                (Some(current), Some(incoming)) => {
+                    let merged = f(&read_recover(current), incoming);
+                    Some(Box::new(merged))
+                }
+                _ => {
+                    warn!(
+                        key = owned_key,
+                        "key set to a different type than its conflict resolver expects, not merging"
+                    );
+                    None
+                }
+            }
+        });
+        lock_recover(&self.resolvers).insert(key.to_string(), resolve);
+    }
+
+    fn resolve_conflict(
+        &self,
+        key: &str,
+        current: &dyn Any,
+        incoming: &dyn Any,
+    ) -> Option<Box<dyn Any + Send>> {
+        let resolve = lock_recover(&self.resolvers).get(key).cloned()?;
+        resolve(current, incoming)
+    }
+
+    // add a step every update from the frontend goes through before it's parsed, in the
+    // order added. a rejection is returned as StateError::Invalid
+    pub fn add_middleware<F>(&self, f: F)
+    where
+        F: Fn(&str, &mut serde_json::Value) -> MiddlewareResult + Send + Sync + 'static,
+    {
+        lock_recover(&self.middleware).push(Arc::new(f));
+    }
+
+    // the value after the middleware chain, untouched (and not even parsed) without any
+    fn run_middleware<'v>(&self, key: &str, value: &'v str) -> Result<Cow<'v, str>, StateError> {
+        // copied out so a middleware can add another without deadlocking
+        let chain = lock_recover(&self.middleware).clone();
+        if chain.is_empty() {
+            return Ok(Cow::Borrowed(value));
+        }
+
+        let mut json: serde_json::Value = serde_json::from_str(value)
+            .map_err(|err| StateError::Deserialize(key.to_string(), err))?;
+        middleware::run(&chain, key, &mut json).map_err(|reason| {
+            debug!(key, reason, "update rejected by middleware");
+            StateError::Invalid(key.to_string(), reason)
+        })?;
+        Ok(Cow::Owned(json.to_string()))
+    }
+
+    fn validate<T: ItemTrait>(&self, key: &str, value: &T) -> Result<(), StateError> {
+        self.validate_erased(key, value)
+    }
+
+    fn has_validator(&self, key: &str) -> bool {
+        lock_recover(&self.validators).contains_key(key)
+    }
+
+    fn validate_erased(&self, key: &str, value: &dyn Any) -> Result<(), StateError> {
+        let Some(validate) = lock_recover(&self.validators).get(key).cloned() else {
+            return Ok(());
+        };
+        validate(value).map_err(|reason| {
+            debug!(key, reason, "value rejected by validator");
+            StateError::Invalid(key.to_string(), reason)
+        })
+    }
+
+    // record that key holds a T without setting a value, so update_string and
+    // emit_registered can handle it before it's ever set
+    pub fn register_type<T: ItemTrait>(&self, key: &str) {
+        let _span = key_span!(key, T);
+        self.register_serializers::<T>(key);
+    }
+
+    // ensure the serializers stored for a key match T, replacing any left over from a different type
+    fn register_serializers<T: ItemTrait>(&self, key: &str) {
+        let mut ds_guard = lock_recover(&self.serializers);
+        if let Some(existing) = ds_guard.get(key) {
+            if existing.type_id == TypeId::of::<T>() {
+                return;
+            }
+            debug!(key, "key changed type, replacing its serializers");
+        } else {
+            debug!(key, "no serializers set for this key yet, adding it");
+        }
+
+        ds_guard.insert(
+            key.to_string(),
+            Serializers::with_codec(self.codec::<T>(key)),
+        );
+    }
+
+    fn codec<T: ItemTrait>(&self, key: &str) -> Codec<T> {
+        Codec::of(&self.codecs, key)
+    }
+
+    // drop the serializers for a key once its value is gone so the map doesn't grow forever
+    fn unregister_serializers(&self, key: &str) {
+        let mut ds_guard = lock_recover(&self.serializers);
+        ds_guard.remove(key);
+    }
+
+    // remove a key from the cache (and disk if persisted), emitting a removed event
+    pub fn remove(&self, key: &str) -> bool {
+        match self.try_remove(key) {
+            Ok(()) => true,
+            Err(err) => {
+                warn!("remove ignored: {}", err);
+                false
+            }
+        }
+    }
+
+    // like remove, but returns KeyNotFound if there was nothing to remove
+    pub fn try_remove(&self, key: &str) -> Result<(), StateError> {
+        let _span = key_span!(key);
+        debug!("remove");
+        let removed = {
+            let mut map_guard = write_recover(&self.data);
+            map_guard.remove(key)
+        };
+        if removed.is_none() {
+            return Err(StateError::KeyNotFound(key.to_string()));
+        }
+
+        self.audit(key, None);
+        self.unregister_serializers(key);
+        lock_recover(&self.versions).remove(key);
+        lock_recover(&self.last_emitted).remove(key);
+        self.forget_json(key);
+        lock_recover(&self.history).forget(key);
+        lock_recover(&self.paused_emits).pending.remove(key);
+        lock_recover(&self.throttled_emits).pending.remove(key);
+        lock_recover(&self.offline_emits).remove(key);
+        lock_recover(&self.rollback).remove(key);
+
+        if self.should_persist(key) {
+            debug!("removing from disk");
+            let _order = lock_recover(&self.disk_order);
+            self.unpersist(key);
+        }
+        lock_recover(&self.ephemeral).remove(key);
+        lock_recover(&self.expiries).remove(key);
+        if self.cfg.max_keys.is_some() {
+            lock_recover(&self.recency).forget(key);
+        }
+
+        let name = format!("{}{}_removed", self.cfg.event_prefix, key);
+        debug!("emitting {}", name);
+        self.emit_or_log(&name, ());
+        Ok(())
+    }
+
+    // check whether a key is currently stored, regardless of its type
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.expire_if_due(key);
+        let guard = read_recover(&self.data);
+        guard.contains_key(key)
+    }
+
+    // the name of the type a key was set with, for debugging a TypeMismatch
+    pub fn type_name_of(&self, key: &str) -> Option<&'static str> {
+        let ds_guard = lock_recover(&self.serializers);
+        ds_guard.get(key).map(|serializers| serializers.type_name)
+    }
+
+    // list every key currently stored
+    pub fn keys(&self) -> Vec<String> {
+        self.sweep_expired();
+        let guard = read_recover(&self.data);
+        guard.keys().cloned().collect()
+    }
+
+    // look up the lock for a key, checking the stored type matches T
+    fn lookup<T: ItemTrait>(&self, key: &str) -> Result<Arc<RwLock<T>>, StateError> {
+        self.expire_if_due(key);
+        let guard = read_recover(&self.data);
+        let ptr = guard
+            .get(key)
+            .ok_or_else(|| StateError::KeyNotFound(key.to_string()))?;
+        // only a key that exists counts as used, a miss shouldn't leave it in the recency list
+        self.touch(key);
+
+        // hand out a shared ref so the value stays alive even if the key is removed
+        ptr.clone()
+            .downcast::<RwLock<T>>()
+            .map_err(|_| StateError::TypeMismatch(key.to_string()))
+    }
+
+    // get a lock protected item that will emit an update event when dropped.
+    // panics if the key is missing or holds a different type, after reporting it
+    pub fn get<'a, T: ItemTrait>(&'a self, key: &'a str) -> Item<'a, T, R> {
+        self.try_get(key).unwrap_or_else(|err| {
+            self.report(&err);
+            panic!("{}", err)
+        })
+    }
+
+    // like get, but returns an error instead of panicking if the key is missing or holds a different type
+    pub fn try_get<'a, T: ItemTrait>(&'a self, key: &'a str) -> Result<Item<'a, T, R>, StateError> {
+        self.item(Cow::Borrowed(key))
+    }
+
+    // get, loading the key or setting it to f() first if it isn't in memory. panics if it
+    // holds a different type, see try_get_or_insert_with
+    pub fn get_or_insert_with<'a, T: ItemTrait, F: FnOnce() -> T>(
+        &'a self,
+        key: &'a str,
+        f: F,
+    ) -> Item<'a, T, R> {
+        self.try_get_or_insert_with(key, f).unwrap_or_else(|err| {
+            self.report(&err);
+            panic!("{}", err)
+        })
+    }
+
+    // like get_or_insert_with, but returns TypeMismatch instead of panicking
+    pub fn try_get_or_insert_with<'a, T: ItemTrait, F: FnOnce() -> T>(
+        &'a self,
+        key: &'a str,
+        f: F,
+    ) -> Result<Item<'a, T, R>, StateError> {
+        self.item_or_insert_with(Cow::Borrowed(key), f)
+    }
+
+    // the value is read or made before the map is locked, so f can use the syncer. when two
+    // callers race only the first one's is stored, the other's f may still have run
+    pub(crate) fn item_or_insert_with<'a, T: ItemTrait, F: FnOnce() -> T>(
+        &'a self,
+        key: Cow<'a, str>,
+        f: F,
+    ) -> Result<Item<'a, T, R>, StateError> {
+        let _span = key_span!(key, T);
+        if !self.contains_key(&key) {
+            let (value, persist) = if !self.should_persist(&key) {
+                (f(), true)
+            } else {
+                match self.read_stored::<T>(&key) {
+                    Ok(value) => (value.unwrap_or_else(f), true),
+                    Err(err) => {
+                        // like load_or, what's on disk may just need a different key
+                        warn!("using fallback value without persisting it");
+                        self.report(&err);
+                        (f(), false)
+                    }
+                }
+            };
+            if !self.insert_if_absent(&key, value, persist) {
+                debug!("key was inserted meanwhile, using that value");
+            }
+        }
+        self.item(key)
+    }
+
+    pub(crate) fn item<'a, T: ItemTrait>(
+        &'a self,
+        key: Cow<'a, str>,
+    ) -> Result<Item<'a, T, R>, StateError> {
+        let _span = key_span!(key, T);
+        debug!("get");
+        let v_ref = self.lookup::<T>(&key)?;
+
+        Ok(Item(v_ref, key, self, AtomicBool::new(false), false))
+    }
+
+    // like get, but the handle owns a clone of the syncer, so it can be held across an await.
+    // panics if the key is missing or holds a different type
+    pub fn owned_handle<T: ItemTrait>(&self, key: &str) -> OwnedItem<T, R> {
+        self.try_owned_handle(key).unwrap_or_else(|err| {
+            self.report(&err);
+            panic!("{}", err)
+        })
+    }
+
+    pub fn try_owned_handle<T: ItemTrait>(&self, key: &str) -> Result<OwnedItem<T, R>, StateError> {
+        let _span = key_span!(key, T);
+        debug!("owned_handle");
+        let v_ref = self.lookup::<T>(key)?;
+        Ok(OwnedItem::new(v_ref, key, self.clone()))
+    }
+
+    // an Item for a value that isn't stored yet, the key is set to it and emitted once the
+    // Item drops
+    pub fn stage<'a, T: ItemTrait>(&'a self, key: &'a str, initial: T) -> Item<'a, T, R> {
+        self.staged_item(Cow::Borrowed(key), initial)
+    }
+
+    pub(crate) fn staged_item<'a, T: ItemTrait>(
+        &'a self,
+        key: Cow<'a, str>,
+        initial: T,
+    ) -> Item<'a, T, R> {
+        let _span = key_span!(key, T);
+        debug!("stage");
+        let v_ref = Arc::new(RwLock::new(initial));
+        Item(v_ref, key, self, AtomicBool::new(false), true)
+    }
+
+    // emit and persist a key whose value was changed through an Item or OwnedItem
+    fn sync_value<T: ItemTrait>(&self, key: &str, v_ref: &RwLock<T>) {
+        // serialize straight from the lock rather than cloning a possibly large value, it's
+        // only copied out when on_change callbacks need it (they may lock the key again)
+        let previous = lock_recover(&self.rollback).remove(key);
+        let (json, value) = {
+            let guard = read_recover(v_ref);
+            debug!("[Item] dropped: {}", self.redact(key, &*guard));
+            if let Err(err) = self.validate(key, &*guard) {
+                drop(guard);
+                if let Some(previous) = previous.and_then(|previous| previous.downcast::<T>().ok())
+                {
+                    *write_recover(v_ref) = *previous;
+                }
+                self.report(&err);
+                return;
+            }
+            let value = self.notifies(key).then(|| guard.clone());
+            (self.codec::<T>(key).encode(&guard), value)
+        };
+
+        self.bump_version(key);
+        let json = match json {
+            Ok(json) => json,
+            Err(err) => {
+                self.report(&StateError::Serialize(key.to_string(), err));
+                return;
+            }
+        };
+        self.cache_json(key, &json);
+        self.record_change(key, || Some(json.clone()));
+        self.emit_update(key, &json);
+        if let Some(value) = value {
+            self.notify_change_erased(key, &value, || json.to_string());
+        }
+
+        // if disk persist is enabled
+        if self.should_persist(key) {
+            debug!("[Item] persisting to disk: {}", key);
+            self.persist_value(key, json);
+        }
+    }
+
+    // store the value of a staged Item under its key, taking the Item's lock as the key's
+    fn store_staged<T: ItemTrait>(&self, key: &str, v_ref: &Arc<RwLock<T>>) {
+        let (json, value) = {
+            let guard = read_recover(v_ref);
+            debug!("[Item] staged value dropped: {}", self.redact(key, &*guard));
+            if let Err(err) = self.validate(key, &*guard) {
+                self.report(&err);
+                return;
+            }
+            let value = self.notifies(key).then(|| guard.clone());
+            (self.codec::<T>(key).encode(&guard), value)
+        };
+        let json = match json {
+            Ok(json) => json,
+            Err(err) => {
+                self.report(&StateError::Serialize(key.to_string(), err));
+                return;
+            }
+        };
+
+        self.register_serializers::<T>(key);
+        lock_recover(&self.expiries).remove(key);
+        {
+            let mut map_guard = write_recover(&self.data);
+            map_guard.insert(key.to_string(), v_ref.clone());
+            self.bump_version(key);
+        }
+        self.cache_json(key, &json);
+        self.record_change(key, || Some(json.clone()));
+        self.touch(key);
+        self.evict_over_capacity(key);
+        self.emit_update(key, &json);
+        if let Some(value) = value {
+            self.notify_change_erased(key, &value, || json.to_string());
+        }
+        if self.should_persist(key) {
+            self.persist_value(key, json);
+        }
+    }
+
+    // a read-only handle to a key, for reading several fields knowing nothing will be emitted.
+    // panics if the key is missing or holds a different type, after reporting it
+    pub fn get_ref<T: ItemTrait>(&self, key: &str) -> ReadItem<T> {
+        self.try_get_ref(key).unwrap_or_else(|err| {
+            self.report(&err);
+            panic!("{}", err)
+        })
+    }
+
+    pub fn try_get_ref<T: ItemTrait>(&self, key: &str) -> Result<ReadItem<T>, StateError> {
+        let _span = key_span!(key, T);
+        debug!("get_ref");
+        self.lookup::<T>(key).map(ReadItem)
+    }
+
+    // a handle to the keys under prefix, see ScopedSyncer
+    pub fn scope<'a>(&'a self, prefix: &str) -> ScopedSyncer<'a, R> {
+        ScopedSyncer::new(self, prefix)
+    }
+
+    // snapshot an Item in the cache as a read-only reference of the current state.
+    // panics if the key is missing or holds a different type, after reporting it
+    pub fn snapshot<'a, T: ItemTrait>(&'a self, key: &'a str) -> T {
+        let _span = key_span!(key, T);
+        debug!("snapshot");
+        let v_ref = self.lookup::<T>(key).unwrap_or_else(|err| {
+            self.report(&err);
+            panic!("{}", err)
+        });
+        let guard = read_recover(&v_ref);
+
+        guard.clone()
+    }
+
+    // like snapshot, but None if the key is missing or holds a different type
+    pub fn try_snapshot<T: ItemTrait>(&self, key: &str) -> Option<T> {
+        let _span = key_span!(key, T);
+        debug!("snapshot");
+        let v_ref = self.lookup::<T>(key).ok()?;
+        let guard = read_recover(&v_ref);
+
+        Some(guard.clone())
+    }
+
+    // like try_snapshot, but returns why it failed, including Poisoned if a panic left the
+    // key poisoned where snapshot would recover it
+    pub fn snapshot_checked<T: ItemTrait>(&self, key: &str) -> Result<T, StateError> {
+        let _span = key_span!(key, T);
+        debug!("snapshot");
+        let v_ref = self.lookup::<T>(key)?;
+        let guard = v_ref
+            .read()
+            .map_err(|_| StateError::Poisoned(key.to_string()))?;
+
+        Ok(guard.clone())
+    }
+
+    // the key's value and its version, read under the same lock, for the frontend to send
+    // back with its next update. None if it's missing or another type
+    pub fn snapshot_versioned<T: ItemTrait>(&self, key: &str) -> Option<(T, u128)> {
+        let _span = key_span!(key, T);
+        debug!("snapshot_versioned");
+        let v_ref = self.lookup::<T>(key).ok()?;
+        let guard = read_recover(&v_ref);
+        let version = self.current_version(key).unwrap_or_default();
+
+        Some((guard.clone(), version))
+    }
+
+    // several keys' values as JSON, all read locked together (in key order) so they agree.
+    // missing keys are left out, as are ones that fail to serialize
+    pub fn snapshot_many(&self, keys: &[&str]) -> HashMap<String, serde_json::Value> {
+        debug!(count = keys.len(), "snapshot_many");
+        for key in keys {
+            self.expire_if_due(key);
+        }
+        let mut keys = keys.to_vec();
+        keys.sort_unstable();
+        keys.dedup();
+
+        let ds_guard = lock_recover(&self.serializers);
+        let map_guard = read_recover(&self.data);
+        let mut locked = Vec::with_capacity(keys.len());
+        for key in keys {
+            let (Some(serializers), Some(entry)) = (ds_guard.get(key), map_guard.get(key)) else {
+                continue;
+            };
+            if let Some(guard) = (serializers.read_lock)(entry.as_ref()) {
+                self.touch(key);
+                locked.push((key, guard));
+            }
+        }
+
+        let mut values = HashMap::with_capacity(locked.len());
+        let mut failed = Vec::new();
+        for (key, guard) in &locked {
+            match guard.to_value() {
+                Ok(value) => {
+                    values.insert(key.to_string(), value);
+                }
+                Err(err) => failed.push(StateError::Serialize(key.to_string(), err)),
+            }
+        }
+        drop(locked);
+        drop(map_guard);
+        drop(ds_guard);
+
+        // on_error may call back into the syncer
+        for err in &failed {
+            self.report(err);
+        }
+        values
+    }
+
+    // run f on a borrow of the key's value, e.g. to read one field without cloning the rest.
+    // f mustn't change the key through the syncer
+    pub fn with<T: ItemTrait, U, F: FnOnce(&T) -> U>(
+        &self,
+        key: &str,
+        f: F,
+    ) -> Result<U, StateError> {
+        let _span = key_span!(key, T);
+        debug!("with");
+        let v_ref = self.lookup::<T>(key)?;
+        let guard = read_recover(&v_ref);
+        Ok(f(&guard))
+    }
+
+    // like snapshot_checked for small Copy values, without going through Clone
+    pub fn read_copy<T: ItemTrait + Copy>(&self, key: &str) -> Result<T, StateError> {
+        self.with(key, |value: &T| *value)
+    }
+
+    // emit an update event for the current item's state.
+    // returns false if the key is missing or holds a different type
+    pub fn emit<T: ItemTrait>(&self, name: &str) -> bool {
+        match self.try_emit::<T>(name) {
+            Ok(emitted) => emitted,
+            Err(err) => {
+                self.report(&err);
+                false
+            }
+        }
+    }
+
+    // like emit, but returns the error if the key holds a different type or the emit fails.
+    // returns false if the key doesn't exist
+    pub fn try_emit<T: ItemTrait>(&self, key: &str) -> Result<bool, StateError> {
+        let _span = key_span!(key, T);
+        debug!("emit");
+        let v_ref = match self.lookup::<T>(key) {
+            Ok(v_ref) => v_ref,
+            Err(StateError::KeyNotFound(_)) => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        // always the whole value, this is what a window hydrates from
+        if let Some(json) = self.cached_json(key) {
+            self.emit_current_to(None, key, &*json)?;
+            return Ok(true);
+        }
+        // encoded from under the read lock, there's no need to copy the value to send it
+        let json = self.encode_locked(key, &v_ref)?;
+        self.emit_current_to(None, key, &json)?;
+        Ok(true)
+    }
+
+    // the JSON Schema for the type registered under a key, e.g. for the frontend to render a
+    // settings form from. None if the key has no type registered
+    #[cfg(feature = "schema")]
+    pub fn schema_for(&self, key: &str) -> Option<serde_json::Value> {
+        let _span = key_span!(key);
+        let ds_guard = lock_recover(&self.serializers);
+        ds_guard.get(key).map(|serializers| (serializers.schema)())
+    }
+
+    // emit every key with a registered type, e.g. to hydrate a new window, returning how many
+    // were emitted
+    pub fn emit_all(&self) -> usize {
+        debug!("emit_all");
+        let mut emitted = 0;
+        for key in self.keys() {
+            match self.emit_registered(&key) {
+                Ok(true) => emitted += 1,
+                Ok(false) => {}
+                Err(err) => self.report(&err),
+            }
+        }
+        emitted
+    }
+
+    // like try_emit, for a key whose type is only known at runtime from its registered
+    // serializers. returns false if the key has no type registered or no value yet
+    pub fn emit_registered(&self, key: &str) -> Result<bool, StateError> {
+        let _span = key_span!(key);
+        debug!("emit_registered");
+        if let Some(json) = self.cached_json(key) {
+            self.emit_current_to(None, key, &*json)?;
+            return Ok(true);
+        }
+        let json = {
+            let ds_guard = lock_recover(&self.serializers);
+            let Some(serializers) = ds_guard.get(key) else {
+                return Ok(false);
+            };
+            let Some(entry) = read_recover(&self.data).get(key).cloned() else {
+                return Ok(false);
+            };
+            (serializers.to_value)(entry.as_ref())
+                .map_err(|err| StateError::Serialize(key.to_string(), err))?
+        };
+
+        self.emit_current_to(None, key, &json)?;
+        Ok(true)
+    }
+
+    // get the current version of a key, bumped on every set/update
+    pub fn current_version(&self, key: &str) -> Option<u128> {
+        let guard = lock_recover(&self.versions);
+        guard.get(key).copied()
+    }
+
+    // the current point in the syncer's history of changes, for modified_since
+    pub fn marker(&self) -> u128 {
+        self.version_counter.load(Ordering::SeqCst) as u128
+    }
+
+    // the keys changed after marker was taken, e.g. to upload what changed since the last
+    // sync. removed keys aren't included
+    pub fn modified_since(&self, marker: u128) -> Vec<String> {
+        let guard = lock_recover(&self.versions);
+        guard
+            .iter()
+            .filter(|(_, version)| **version > marker)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    // versions come from one counter shared by every key, so a key that is removed and
+    // set again never goes back to a version the frontend has already seen
+    fn bump_version(&self, key: &str) -> u128 {
+        let version = self.version_counter.fetch_add(1, Ordering::SeqCst) as u128 + 1;
+        let mut guard = lock_recover(&self.versions);
+        guard.insert(key.to_string(), version);
+        version
+    }
+
+    // update a key, only emitting the change to the window with the given label
+    pub fn update_to_window<T: ItemTrait>(
+        &self,
+        label: &str,
+        key: &str,
+        value: T,
+    ) -> Result<bool, StateError> {
+        self.check_window(label)?;
+        let json = match self.update_inner(key, value, None, false) {
+            Ok((_, Some(json))) => json,
+            // a new key was inserted without being serialized, so encode what was stored
+            Ok((_, None)) => {
+                let v_ref = self.lookup::<T>(key)?;
+                Arc::new(self.encode_locked(key, &v_ref)?)
+            }
+            Err(err) => {
+                self.report(&err);
+                return Ok(false);
+            }
+        };
+        self.emit_update_to(Some(label), key, &*json)?;
+        Ok(true)
+    }
+
+    // emit an update event for the current item's state to the window with the given label
+    pub fn emit_to_window<T: ItemTrait>(&self, label: &str, key: &str) -> Result<(), StateError> {
+        let _span = key_span!(key, T);
+        debug!(label, "emit_to_window");
+        self.check_window(label)?;
+        let v_ref = self.lookup::<T>(key)?;
+        if let Some(json) = self.cached_json(key) {
+            return self.emit_current_to(Some(label), key, &*json);
+        }
+        let json = self.encode_locked(key, &v_ref)?;
+        self.emit_current_to(Some(label), key, &json)
+    }
+
+    fn encode_locked<T: ItemTrait>(
+        &self,
+        key: &str,
+        v_ref: &RwLock<T>,
+    ) -> Result<serde_json::Value, StateError> {
+        self.codec::<T>(key)
+            .encode(&read_recover(v_ref))
+            .map_err(|err| StateError::Serialize(key.to_string(), err))
+    }
+
+    fn check_window(&self, label: &str) -> Result<(), StateError> {
+        if self.sink.has_window(label) {
+            Ok(())
+        } else {
+            Err(StateError::WindowNotFound(label.to_string()))
+        }
+    }
+
+    fn event_name(&self, key: &str) -> String {
+        format!("{}{}{}", self.cfg.event_prefix, key, self.cfg.event_suffix)
+    }
+
+    // emit a change to every window. in EmitMode::Patch only the fields that changed since the
+    // last full or patch emit are sent, as a {event_prefix}{key}_patch event
+    fn emit_update<T: Serialize + Debug>(&self, key: &str, value: &T) {
+        if self.hold_emit(key, value) || self.throttle_emit(key, value) {
+            return;
+        }
+        self.send_update(key, value);
+    }
+
+    fn send_update<T: Serialize + Debug>(&self, key: &str, value: &T) {
+        if self.queue_offline(key, value) {
+            return;
+        }
+        // there are windows again, send anything queued before on_run_event gets to it
+        self.replay_offline();
+        if self.cfg.emit_mode == EmitMode::Patch
+            && let Ok(json) = serde_json::to_value(value)
+        {
+            let mut last_emitted = lock_recover(&self.last_emitted);
+            // a patch would drop a null field, so that value is sent whole
+            if let Some(previous) = last_emitted.get(key)
+                && !patch::has_null_field(&json)
+            {
+                let delta = patch::diff(previous, &json);
+                last_emitted.insert(key.to_string(), json);
+                drop(last_emitted);
+                if !patch::is_empty(&delta) {
+                    self.emit_patch(key, delta);
+                }
+                return;
+            }
+        }
+
+        if let Err(err) = self.emit_update_to(None, key, value) {
+            self.report(&err);
+        }
+    }
+
+    // queue a change event while there are no windows, where it would reach nobody and a
+    // window created afterwards would start out stale. returns whether it was queued
+    fn queue_offline<T: Serialize>(&self, key: &str, value: &T) -> bool {
+        if self.sink.has_windows() {
+            return false;
+        }
+        match serde_json::to_value(value) {
+            Ok(json) => {
+                debug!(key, "no windows, queueing the change event");
+                lock_recover(&self.offline_emits).insert(key.to_string(), json);
+                true
+            }
+            // let the regular emit path report it
+            Err(_) => false,
+        }
+    }
+
+    fn replay_offline(&self) {
+        let pending = {
+            let mut offline = lock_recover(&self.offline_emits);
+            if offline.is_empty() || !self.sink.has_windows() {
+                return;
+            }
+            std::mem::take(&mut *offline)
+        };
+
+        debug!(
+            count = pending.len(),
+            "window created, replaying queued change events"
+        );
+        for (key, value) in pending {
+            self.send_update(&key, &value);
+        }
+    }
+
+    // stop sending change events until resume_emits, which emits each changed key once.
+    // explicit emits and remove events aren't held back
+    pub fn pause_emits(&self) {
+        debug!("pause_emits");
+        lock_recover(&self.paused_emits).depth += 1;
+    }
+
+    pub fn resume_emits(&self) {
+        let pending = {
+            let mut paused = lock_recover(&self.paused_emits);
+            if paused.depth == 0 {
+                warn!("resume_emits called without a matching pause_emits, ignoring");
+                return;
+            }
+            paused.depth -= 1;
+            if paused.depth > 0 {
+                return;
+            }
+            std::mem::take(&mut paused.pending)
+        };
+
+        debug!(count = pending.len(), "resume_emits");
+        for (key, value) in pending {
+            self.emit_update(&key, &value);
+        }
+    }
+
+    // run f with emits paused, resuming afterwards even if it panics
+    pub fn with_emits_paused<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        struct Resume<'s, R: Runtime>(&'s StateSyncer<R>);
+        impl<R: Runtime> Drop for Resume<'_, R> {
+            fn drop(&mut self) {
+                self.0.resume_emits();
+            }
+        }
+
+        self.pause_emits();
+        let _resume = Resume(self);
+        f()
+    }
+
+    fn emits_paused(&self) -> bool {
+        lock_recover(&self.paused_emits).depth > 0
+    }
+
+    // queue a change event while emits are paused, returning whether it was held back
+    fn hold_emit<T: Serialize>(&self, key: &str, value: &T) -> bool {
+        let mut paused = lock_recover(&self.paused_emits);
+        if paused.depth == 0 {
+            return false;
+        }
+        match serde_json::to_value(value) {
+            Ok(json) => {
+                paused.pending.insert(key.to_string(), json);
+                true
+            }
+            Err(err) => {
+                drop(paused);
+                self.report(&StateError::Serialize(key.to_string(), err));
+                true
+            }
+        }
+    }
+
+    // queue a change event until the key's throttle interval is up, returning whether it was
+    // queued. the first change in an interval starts it, later ones only replace the value
+    fn throttle_emit<T: Serialize>(&self, key: &str, value: &T) -> bool {
+        let Some(interval) = self.cfg.emit_throttle else {
+            return false;
+        };
+        let json = match serde_json::to_value(value) {
+            Ok(json) => json,
+            // let the regular emit path report it
+            Err(_) => return false,
+        };
+
+        let mut throttled = lock_recover(&self.throttled_emits);
+        let deadline = std::time::Instant::now() + interval;
+        throttled
+            .pending
+            .entry(key.to_string())
+            .and_modify(|(_, pending)| *pending = json.clone())
+            .or_insert((deadline, json));
+        if throttled.running {
+            return true;
+        }
+
+        // the thread holds its own handle, so a flush on drop waits for it to finish
+        let syncer = self.clone();
+        let spawned = std::thread::Builder::new()
+            .name("state-syncer-throttle".to_owned())
+            .spawn(move || syncer.send_throttled());
+        match spawned {
+            Ok(_) => {
+                throttled.running = true;
+                true
+            }
+            Err(err) => {
+                error!(
+                    "unable to spawn emit throttle thread, emitting now: {}",
+                    err
+                );
+                throttled.pending.remove(key);
+                false
+            }
+        }
+    }
+
+    // send each throttled emit once it's due, until none are left
+    fn send_throttled(&self) {
+        loop {
+            let (due, next) = {
+                let mut throttled = lock_recover(&self.throttled_emits);
+                let now = std::time::Instant::now();
+                let mut due = Vec::new();
+                throttled.pending.retain(|key, (deadline, value)| {
+                    if *deadline > now {
+                        return true;
+                    }
+                    due.push((key.clone(), value.take()));
+                    false
+                });
+                let next = throttled
+                    .pending
+                    .values()
+                    .map(|(deadline, _)| *deadline)
+                    .min();
+                if due.is_empty() && next.is_none() {
+                    throttled.running = false;
+                    return;
+                }
+                (due, next)
+            };
+
+            for (key, value) in due {
+                self.send_update(&key, &value);
+            }
+            if let Some(next) = next {
+                std::thread::sleep(next.saturating_duration_since(std::time::Instant::now()));
+            }
+        }
+    }
+
+    fn emit_patch(&self, key: &str, delta: serde_json::Value) {
+        let name = format!("{}{}_patch", self.cfg.event_prefix, key);
+        let version = self.current_version(key).unwrap_or_default();
+        debug!("emitting {}: {}", name, self.redact(key, &delta));
+        let payload = self.versioned(version, delta);
+        self.emit_or_log(&name, payload);
+    }
+
+    // emit the whole value to every window, or only the window with the given label
+    fn emit_update_to<T: Serialize + Debug>(
+        &self,
+        window: Option<&str>,
+        key: &str,
+        value: &T,
+    ) -> Result<(), StateError> {
+        self.emit_value_to(window, key, self.event_name(key), value)
+    }
+
+    // a key's current value for a window to start from rather than a change to it, sent as
+    // {event_prefix}{key}_init with init_events set
+    fn emit_current_to<T: Serialize + Debug>(
+        &self,
+        window: Option<&str>,
+        key: &str,
+        value: &T,
+    ) -> Result<(), StateError> {
+        let name = if self.cfg.init_events {
+            format!("{}{}_init", self.cfg.event_prefix, key)
+        } else {
+            self.event_name(key)
+        };
+        self.emit_value_to(window, key, name, value)
+    }
+
+    fn emit_value_to<T: Serialize + Debug>(
+        &self,
+        window: Option<&str>,
+        key: &str,
+        name: String,
+        value: &T,
+    ) -> Result<(), StateError> {
+        // later patches are diffed against what every window was last sent
+        if window.is_none()
+            && self.cfg.emit_mode == EmitMode::Patch
+            && let Ok(json) = serde_json::to_value(value)
+        {
+            lock_recover(&self.last_emitted).insert(key.to_string(), json);
+        }
+
+        let version = self.current_version(key).unwrap_or_default();
+        debug!("emitting {}: {}", name, self.redact(key, value));
+        let payload = self.versioned(version, value);
+        self.emit_event(window, &name, payload)
+    }
+
+    // wrap a value for an event, stamped with the next sequence number and the current time
+    fn versioned<T>(&self, version: u128, value: T) -> VersionedValue<T> {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|since| since.as_millis() as u64)
+            .ok();
+        VersionedValue {
+            version,
+            value,
+            origin: self.origin.to_string(),
+            seq: Some(self.emit_seq.fetch_add(1, Ordering::Relaxed) + 1),
+            timestamp,
+        }
+    }
+
+    fn emit_event<S: Serialize>(
+        &self,
+        window: Option<&str>,
+        name: &str,
+        payload: S,
+    ) -> Result<(), StateError> {
+        let payload = serde_json::to_string(&payload)
+            .map_err(|err| StateError::Serialize(name.to_string(), err))?;
+        let mut attempt = 0;
+        loop {
+            match self.sink.emit(window, name, &payload) {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < self.cfg.emit_retries => {
+                    let backoff = EMIT_RETRY_BACKOFF
+                        .saturating_mul(2u32.saturating_pow(attempt.into()))
+                        .min(EMIT_RETRY_BACKOFF_MAX);
+                    debug!(
+                        name,
+                        attempt, "emit failed, retrying in {:?}: {}", backoff, err
+                    );
+                    std::thread::sleep(backoff);
+                    attempt += 1;
+                }
+                Err(err) => return Err(StateError::Emit(err)),
+            }
+        }
+    }
+
+    // for emits that are a side effect of a change, which has already been applied by then.
+    // failing to emit (e.g. while a window is closing) is only reported
+    fn emit_or_log<S: Serialize>(&self, name: &str, payload: S) {
+        if let Err(err) = self.emit_event(None, name, payload) {
+            self.report(&err);
+        }
+    }
+
+    // handle an error that has no caller to return it to, according to cfg.on_error.
+    // a stale update is expected when windows race, so it's only logged
+    pub fn report(&self, err: &StateError) {
+        if let StateError::Stale(_) = err {
+            warn!("rejecting {}", err);
+            return;
+        }
+        match &self.cfg.on_error {
+            // panicking while already unwinding (e.g. from Item's destructor) would abort
+            ErrorPolicy::Panic if !std::thread::panicking() => panic!("{}", err),
+            ErrorPolicy::Panic | ErrorPolicy::Log => error!("{}", err),
+            ErrorPolicy::Callback(callback) => callback(err),
+        }
+    }
+
+    // run a callback with the new value whenever the key changes, after it's stored.
+    // the callback stays registered until the returned Subscription is dropped
+    pub fn on_change<T, F>(&self, key: &str, callback: F) -> Subscription
+    where
+        T: ItemTrait,
+        F: Fn(&T) + Send + Sync + 'static,
+    {
+        let _span = key_span!(key, T);
+        let owned_key = key.to_string();
+        let f: ChangeFn = Arc::new(move |value: &dyn Any| match value.downcast_ref::<T>() {
+            Some(value) => callback(value),
+            None => warn!(
+                key = owned_key,
+                "key changed to a different type than its on_change callback expects"
+            ),
+        });
+        let id = lock_recover(&self.observers).add(key, f);
+        Subscription::new(id, &self.observers)
+    }
+
+    // run a callback with the key and its new value as JSON whenever any key changes.
+    // callbacks run in the order they were registered, after the key's on_change callbacks
+    pub fn on_any_change<F>(&self, callback: F) -> Subscription
+    where
+        F: Fn(&str, &str) + Send + Sync + 'static,
+    {
+        self.on_prefix_change("", callback)
+    }
+
+    // like on_any_change, but only for keys starting with prefix, e.g. "window.main."
+    // to watch every key under it
+    pub fn on_prefix_change<F>(&self, prefix: &str, callback: F) -> Subscription
+    where
+        F: Fn(&str, &str) + Send + Sync + 'static,
+    {
+        let f: AnyChangeFn = Arc::new(callback);
+        let id = lock_recover(&self.observers).add_prefix(prefix, f);
+        Subscription::new(id, &self.observers)
+    }
+
+    fn notify_change<T: Serialize + Any>(&self, key: &str, value: &T) {
+        self.notify_change_erased(key, value, || {
+            serde_json::to_string(value).unwrap_or_default()
+        });
+    }
+
+    // a stream of every change for async tasks. a receiver that falls behind gets
+    // RecvError::Lagged, the syncer never waits on it
+    #[cfg(feature = "tokio")]
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<StateUpdate> {
+        self.updates.subscribe()
+    }
+
+    // whether a change to key has any callback or subscriber to notify
+    fn notifies(&self, key: &str) -> bool {
+        self.has_subscribers() || lock_recover(&self.observers).watches(key)
+    }
+
+    #[cfg(feature = "tokio")]
+    fn has_subscribers(&self) -> bool {
+        self.updates.receiver_count() > 0
+    }
+
+    #[cfg(not(feature = "tokio"))]
+    fn has_subscribers(&self) -> bool {
+        false
+    }
+
+    // run the callbacks for a key. never call this with a lock held,
+    // the callbacks are free to use the syncer
+    fn notify_change_erased(&self, key: &str, value: &dyn Any, json: impl FnOnce() -> String) {
+        let (callbacks, any_callbacks) = {
+            let observers = lock_recover(&self.observers);
+            (observers.for_key(key), observers.for_prefixes_of(key))
+        };
+        for callback in callbacks {
+            callback(value);
+        }
+
+        let broadcast = self.has_subscribers();
+        if any_callbacks.is_empty() && !broadcast {
+            return;
+        }
+        let json = json();
+        for callback in any_callbacks {
+            callback(key, &json);
+        }
+
+        #[cfg(feature = "tokio")]
+        if broadcast {
+            // only fails when every receiver has gone away in the meantime
+            let _ = self.updates.send(StateUpdate {
+                version: self.current_version(key),
+                name: key.to_string(),
+                value: json,
+                origin: Some(self.origin.to_string()),
+            });
+        }
+    }
+
+    // parse the values for keys being watched so they can be notified once the
+    // type-erased update is stored
+    fn observed_values<'v>(
+        &self,
+        serializers: &SerializersMap,
+        updates: impl Iterator<Item = (&'v String, &'v serde_json::Value)>,
+    ) -> Vec<(String, Box<dyn Any + Send>, String)> {
+        let broadcast = self.has_subscribers();
+        let observers = lock_recover(&self.observers);
+        updates
+            .filter(|(key, _)| broadcast || observers.watches(key))
+            .filter_map(|(key, value)| {
+                let json = value.to_string();
+                let parsed = (serializers.get(key)?.from_str)(&json).ok()?;
+                Some((key.clone(), parsed, json))
+            })
+            .collect()
+    }
+
+    // stop logging the value of a key, e.g. for tokens and API keys
+    pub fn mark_sensitive(&self, key: &str) {
+        lock_recover(&self.sensitive).insert(key.to_string());
+    }
+
+    pub fn is_sensitive(&self, key: &str) -> bool {
+        lock_recover(&self.sensitive).contains(key)
+    }
+
+    // like debug_val, but always hides the value of a key marked sensitive
+    pub fn redact<T: Debug>(&self, key: &str, val: &T) -> String {
+        if self.is_sensitive(key) {
+            "<redacted>".to_string()
+        } else {
+            debug_val(val)
+        }
+    }
+
+    // the id this syncer tags its events with, so it can recognize its own updates coming back
+    pub fn origin_id(&self) -> &str {
+        &self.origin
+    }
+}
+
+// flush buffered writes once the last handle to the syncer goes away
+impl<R: Runtime> Drop for StateSyncer<R> {
+    fn drop(&mut self) {
+        if !self.is_last_handle() {
+            return;
+        }
+
+        let persists = self.cfg.default_persist || self.cfg.persist_keys.values().any(|p| *p);
+        if !persists || self.current_disk().is_none() {
+            return;
+        }
+
+        if let Err(err) = self.flush() {
+            error!("failed to flush state on drop: {}", err);
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! state_handlers {
+    // commands that look up each key's type at runtime, see StateSyncer::register_type
+    (@dynamic $emit:ident, $update:ident, $hydrate:ident, $syncer:ty) => {
+        #[tauri::command]
+        #[specta::specta]
+        fn $emit(name: String, state_syncer: tauri::State<'_, $syncer>) -> bool {
+            $crate::log::info!("{}: {:?}", stringify!($emit), name);
+
+            match state_syncer.emit_registered(&name) {
+                Ok(emitted) => emitted,
+                Err(err) => {
+                    state_syncer.report(&err);
+                    false
+                }
+            }
+        }
+
+        #[tauri::command]
+        #[specta::specta]
+        fn $update(state: $crate::StateUpdate, state_syncer: tauri::State<'_, $syncer>) -> bool {
+            $crate::log::info!("{}: {}", stringify!($update), state_syncer.redact(&state.name, &state));
+
+            match state_syncer.update_string(&state.name, state.value.as_str(), state.version, true) {
+                Ok(()) => true,
+                Err(err) => {
+                    state_syncer.report(&err);
+                    false
+                }
+            }
+        }
+
+        // emits every key, see StateSyncer::emit_all. not every app registers it
+        #[allow(dead_code)]
+        #[tauri::command]
+        #[specta::specta]
+        fn $hydrate(state_syncer: tauri::State<'_, $syncer>) {
+            let emitted = state_syncer.emit_all();
+            $crate::log::info!("{}: emitted {} keys", stringify!($hydrate), emitted);
+        }
+    };
+    // commands for the keys of a #[derive(SyncedState)] struct, see SyncedState
+    (@derive $emit:ident, $update:ident, $hydrate:ident, $syncer:ty, $runtime:ty; $state:ty) => {
+        #[tauri::command]
+        #[specta::specta]
+        fn $emit(name: String, state_syncer: tauri::State<'_, $syncer>) -> bool {
+            $crate::log::info!("{}: {:?}", stringify!($emit), name);
+
+            let syncer: &$crate::StateSyncer<$runtime> = &state_syncer;
+            <$state as $crate::SyncedState>::emit(syncer, &name)
+        }
+
+        #[tauri::command]
+        #[specta::specta]
+        fn $update(state: $crate::StateUpdate, state_syncer: tauri::State<'_, $syncer>) -> bool {
+            $crate::log::info!("{}: {}", stringify!($update), state_syncer.redact(&state.name, &state));
+
+            let syncer: &$crate::StateSyncer<$runtime> = &state_syncer;
+            let result = <$state as $crate::SyncedState>::update(
+                syncer,
+                &state.name,
+                state.value.as_str(),
+                state.version,
+                true,
+            );
+            match result {
+                Some(Ok(())) => true,
+                Some(Err(err)) => {
+                    syncer.report(&err);
+                    false
+                }
+                None => {
+                    $crate::log::warn!("unknown type");
+                    false
+                }
+            }
+        }
+
+        // emits every key, see StateSyncer::emit_all. not every app registers it
+        #[allow(dead_code)]
+        #[tauri::command]
+        #[specta::specta]
+        fn $hydrate(state_syncer: tauri::State<'_, $syncer>) {
+            let emitted = state_syncer.emit_all();
+            $crate::log::info!("{}: emitted {} keys", stringify!($hydrate), emitted);
+        }
+    };
+    (runtime = $runtime:ty; derive $state:ty) => {
+        $crate::state_handlers!(
+            @derive emit_state, update_state, hydrate_all, $crate::StateSyncer<$runtime>, $runtime;
+            $state
+        );
+    };
+    ($name:ident: $syncer:ty; derive $state:ty) => {
+        $crate::paste::paste! {
+            $crate::state_handlers!(
+                @derive [<$name _emit_state>], [<$name _update_state>], [<$name _hydrate_all>], $syncer, tauri::Wry;
+                $state
+            );
+        }
+    };
+    (derive $state:ty) => {
+        $crate::state_handlers!(runtime = tauri::Wry; derive $state);
+    };
+    (runtime = $runtime:ty; dynamic) => {
+        $crate::state_handlers!(
+            @dynamic emit_state, update_state, hydrate_all, $crate::StateSyncer<$runtime>
+        );
+    };
+    (dynamic) => {
+        $crate::state_handlers!(runtime = tauri::Wry; dynamic);
+    };
+    (@commands $emit:ident, $update:ident, $hydrate:ident, $syncer:ty; $($state_type:ident = $state_name:expr),* $(,)?) => {
+        #[tauri::command]
+        #[specta::specta]
+        fn $emit(name: String, state_syncer: tauri::State<'_, $syncer>) -> bool {
+            $crate::log::info!("{}: {:?}", stringify!($emit), name);
+
+            match name.as_str() {
+                $(
+                    $state_name => state_syncer.emit::<$state_type>($state_name),
+                )*
+                _ => return false,
+            }
+        }
+
+        #[tauri::command]
+        #[specta::specta]
+        fn $update(state: $crate::StateUpdate, state_syncer: tauri::State<'_, $syncer>) -> bool {
+            $crate::log::info!("{}: {}", stringify!($update), state_syncer.redact(&state.name, &state));
+
+            let result = match state.name.as_str() {
+                $(
+                    $state_name => state_syncer.update_typed_string::<$state_type>(
+                        $state_name,
+                        state.value.as_str(),
+                        state.version,
+                        true,
+                    ),
+                )*
+                _ => {
+                    $crate::log::warn!("unknown type");
+                    return false;
+                }
+            };
+            match result {
+                Ok(()) => true,
+                Err(err) => {
+                    state_syncer.report(&err);
+                    false
+                }
+            }
+        }
+
+        // emits every key, see StateSyncer::emit_all. not every app registers it
+        #[allow(dead_code)]
+        #[tauri::command]
+        #[specta::specta]
+        fn $hydrate(state_syncer: tauri::State<'_, $syncer>) {
+            let emitted = state_syncer.emit_all();
+            $crate::log::info!("{}: emitted {} keys", stringify!($hydrate), emitted);
+        }
+    };
+    (runtime = $runtime:ty; $($state_type:ident = $state_name:expr),* $(,)?) => {
+        $crate::state_handlers!(
+            @commands emit_state, update_state, hydrate_all, $crate::StateSyncer<$runtime>;
+            $($state_type = $state_name),*
+        );
+    };
+    // for apps with more than one syncer, each its own type that derefs to StateSyncer and
+    // gets commands named {name}_emit_state, {name}_update_state and {name}_hydrate_all
+    ($name:ident: $syncer:ty; $($state_type:ident = $state_name:expr),* $(,)?) => {
+        $crate::paste::paste! {
+            $crate::state_handlers!(
+                @commands [<$name _emit_state>], [<$name _update_state>], [<$name _hydrate_all>], $syncer;
+                $($state_type = $state_name),*
+            );
+        }
+    };
+    ($($state_type:ident = $state_name:expr),* $(,)?) => {
+        $crate::state_handlers!(runtime = tauri::Wry; $($state_type = $state_name),*);
+    };
+}
+
+#[macro_export]
 macro_rules! state_listener {
+    ($app:expr, $syncer:expr, derive $state:ty) => {
+        <$crate::StateUpdate as tauri_specta::Event>::listen(&$app, move |event| {
+            $crate::log::debug!("state update handler: {}", $syncer.redact(&event.payload.name, &event.payload));
+
+            // skip our own updates echoing back so multiple windows don't ping-pong forever
+            if event.payload.origin.as_deref() == Some($syncer.origin_id()) {
+                return;
+            }
+
+            let result = <$state as $crate::SyncedState>::update(
+                &$syncer,
+                &event.payload.name,
+                event.payload.value.as_str(),
+                event.payload.version,
+                false,
+            );
+            if let Some(Err(err)) = result {
+                $syncer.report(&err);
+            }
+        });
+    };
+    ($app:expr, $syncer:expr, dynamic) => {
+        <$crate::StateUpdate as tauri_specta::Event>::listen(&$app, move |event| {
+            $crate::log::debug!("state update handler: {}", $syncer.redact(&event.payload.name, &event.payload));
+
+            // skip our own updates echoing back so multiple windows don't ping-pong forever
+            if event.payload.origin.as_deref() == Some($syncer.origin_id()) {
+                return;
+            }
+
+            if let Err(err) = $syncer.update_string(
+                &event.payload.name,
+                event.payload.value.as_str(),
+                event.payload.version,
+                false,
+            ) {
+                $syncer.report(&err);
+            }
+        });
+    };
     ($app:expr, $syncer:expr, $($state_type:ident = $state_name:expr),* $(,)?) => {
-        StateUpdate::listen(&$app, move |event| {
-            warn!("state update handler: {}", tauri_svelte_synced_store::debug_val(&event.payload));
+        <$crate::StateUpdate as tauri_specta::Event>::listen(&$app, move |event| {
+            $crate::log::debug!("state update handler: {}", $syncer.redact(&event.payload.name, &event.payload));
+
+            // skip our own updates echoing back so multiple windows don't ping-pong forever
+            if event.payload.origin.as_deref() == Some($syncer.origin_id()) {
+                return;
+            }
 
             match event.payload.name.as_str() {
                 $(
                     $state_name => {
-                        $syncer.update_typed_string::<$state_type>(
+                        if let Err(err) = $syncer.update_typed_string::<$state_type>(
                             $state_name,
                             event.payload.value.as_str(),
+                            event.payload.version,
                             false,
-                        );
+                        ) {
+                            $syncer.report(&err);
+                        }
                     }
                 )*
                 _ => return,
@@ -396,3 +4272,132 @@ macro_rules! state_listener {
         });
     };
 }
+
+// state_handlers! and state_listener! from one list, wire the listener up by calling the
+// generated listen_state(app.handle(), syncer.clone()) from setup
+#[macro_export]
+macro_rules! synced_state {
+    (@listener $listen:ident, $syncer:ty, $runtime:ty; derive $state:ty) => {
+        fn $listen(app: &tauri::AppHandle<$runtime>, syncer: $syncer) {
+            $crate::state_listener!(*app, syncer, derive $state);
+        }
+    };
+    (runtime = $runtime:ty; derive $state:ty) => {
+        $crate::state_handlers!(runtime = $runtime; derive $state);
+        $crate::synced_state!(
+            @listener listen_state, $crate::StateSyncer<$runtime>, $runtime;
+            derive $state
+        );
+    };
+    ($name:ident: $syncer:ty; derive $state:ty) => {
+        $crate::state_handlers!($name: $syncer; derive $state);
+        $crate::paste::paste! {
+            $crate::synced_state!(
+                @listener [<$name _listen_state>], $syncer, tauri::Wry;
+                derive $state
+            );
+        }
+    };
+    (derive $state:ty) => {
+        $crate::synced_state!(runtime = tauri::Wry; derive $state);
+    };
+    (@listener $listen:ident, $syncer:ty, $runtime:ty; $($state_type:ident = $state_name:expr),* $(,)?) => {
+        fn $listen(app: &tauri::AppHandle<$runtime>, syncer: $syncer) {
+            // so the dynamic commands and update_string know every key up front too
+            $(
+                syncer.register_type::<$state_type>($state_name);
+            )*
+            $crate::state_listener!(*app, syncer, $($state_type = $state_name),*);
+        }
+    };
+    (runtime = $runtime:ty; $($state_type:ident = $state_name:expr),* $(,)?) => {
+        $crate::state_handlers!(runtime = $runtime; $($state_type = $state_name),*);
+        $crate::synced_state!(
+            @listener listen_state, $crate::StateSyncer<$runtime>, $runtime;
+            $($state_type = $state_name),*
+        );
+    };
+    // see the named form of state_handlers!, the listener is {name}_listen_state
+    ($name:ident: $syncer:ty; $($state_type:ident = $state_name:expr),* $(,)?) => {
+        $crate::state_handlers!($name: $syncer; $($state_type = $state_name),*);
+        $crate::paste::paste! {
+            $crate::synced_state!(
+                @listener [<$name _listen_state>], $syncer, tauri::Wry;
+                $($state_type = $state_name),*
+            );
+        }
+    };
+    ($($state_type:ident = $state_name:expr),* $(,)?) => {
+        $crate::synced_state!(runtime = tauri::Wry; $($state_type = $state_name),*);
+    };
+}
+
+// typed events for tauri-specta per state type ({Type}Update, Init, Patch, Removed) plus
+// BatchUpdate, e.g. state_events!(prefix = "app:", suffix = ":changed"; Settings = "settings")
+#[macro_export]
+macro_rules! state_events {
+    (prefix = $prefix:expr, suffix = $suffix:expr; $($state_type:ident = $state_name:expr),* $(,)?) => {
+        $crate::paste::paste! {
+            $(
+                #[derive(serde::Serialize, serde::Deserialize, specta::Type, Clone, Debug)]
+                pub struct [<$state_type Update>](pub $crate::VersionedValue<$state_type>);
+
+                impl tauri_specta::Event for [<$state_type Update>] {
+                    const NAME: &'static str = concat!($prefix, $state_name, $suffix);
+                }
+
+                #[derive(serde::Serialize, serde::Deserialize, specta::Type, Clone, Debug)]
+                pub struct [<$state_type Init>](pub $crate::VersionedValue<$state_type>);
+
+                impl tauri_specta::Event for [<$state_type Init>] {
+                    const NAME: &'static str = concat!($prefix, $state_name, "_init");
+                }
+
+                // the fields that changed, see EmitMode::Patch
+                #[derive(serde::Serialize, serde::Deserialize, specta::Type, Clone, Debug)]
+                pub struct [<$state_type Patch>](pub $crate::VersionedValue<$crate::serde_json::Value>);
+
+                impl tauri_specta::Event for [<$state_type Patch>] {
+                    const NAME: &'static str = concat!($prefix, $state_name, "_patch");
+                }
+
+                #[derive(serde::Serialize, serde::Deserialize, specta::Type, Clone, Debug)]
+                pub struct [<$state_type Removed>];
+
+                impl tauri_specta::Event for [<$state_type Removed>] {
+                    const NAME: &'static str = concat!($prefix, $state_name, "_removed");
+                }
+            )*
+
+            // every key update_batch changed, by key
+            #[derive(serde::Serialize, serde::Deserialize, specta::Type, Clone, Debug)]
+            pub struct BatchUpdate(
+                pub std::collections::HashMap<String, $crate::VersionedValue<$crate::serde_json::Value>>,
+            );
+
+            impl tauri_specta::Event for BatchUpdate {
+                const NAME: &'static str = concat!($prefix, "batch_update");
+            }
+
+            fn state_events<R: tauri::Runtime>(
+                builder: tauri_specta::Builder<R>,
+            ) -> tauri_specta::Builder<R> {
+                builder.events(tauri_specta::collect_events![
+                    BatchUpdate,
+                    $(
+                        [<$state_type Update>],
+                        [<$state_type Init>],
+                        [<$state_type Patch>],
+                        [<$state_type Removed>]
+                    ),*
+                ])
+            }
+        }
+    };
+    (prefix = $prefix:expr; $($state_type:ident = $state_name:expr),* $(,)?) => {
+        $crate::state_events!(prefix = $prefix, suffix = "_update"; $($state_type = $state_name),*);
+    };
+    ($($state_type:ident = $state_name:expr),* $(,)?) => {
+        $crate::state_events!(prefix = "", suffix = "_update"; $($state_type = $state_name),*);
+    };
+}
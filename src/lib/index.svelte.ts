@@ -2,45 +2,201 @@
 import { listen, type UnlistenFn } from '@tauri-apps/api/event';
 import { invoke } from '@tauri-apps/api/core';
 
+export type VersionedValue<T> = {
+	version: number;
+	value: T;
+	origin: string;
+	// counts up across every event the backend sends, missing from older backends
+	seq?: number;
+	// when the event was sent, in milliseconds since the unix epoch
+	timestamp?: number;
+};
+
+// must match event_prefix/event_suffix in the backend's StateSyncerConfig
+export type EventNaming = {
+	prefix?: string;
+	suffix?: string;
+	// the name given to state_handlers! for a named syncer, whose commands are
+	// {syncer}_emit_state, {syncer}_update_state and {syncer}_hydrate_all
+	syncer?: string;
+};
+
+let debugging = false;
+
+// log every event and sync to the console, off by default
+export function setDebug(enabled: boolean) {
+	debugging = enabled;
+}
+
+function debug(...args: unknown[]) {
+	if (debugging) {
+		console.log('[SyncedStore]:', ...args);
+	}
+}
+
+// applies an RFC 7386 JSON Merge Patch, as sent in {name}_patch events when the backend uses
+// EmitMode::Patch
+function mergePatch(target: unknown, patch: unknown): unknown {
+	if (patch === null || typeof patch !== 'object' || Array.isArray(patch)) {
+		return patch;
+	}
+	const result: Record<string, unknown> =
+		target !== null && typeof target === 'object' && !Array.isArray(target)
+			? { ...(target as Record<string, unknown>) }
+			: {};
+	for (const [field, value] of Object.entries(patch)) {
+		if (value === null) {
+			delete result[field];
+		} else {
+			result[field] = mergePatch(result[field], value);
+		}
+	}
+	return result;
+}
+
+// ask the backend to emit every key it holds, e.g. once a new window has created its
+// SyncedStates. needs the hydrate_all command generated by state_handlers! to be registered
+export function hydrateAll(naming?: EventNaming): Promise<void> {
+	const commands = naming?.syncer ? `${naming.syncer}_` : '';
+	return invoke(`${commands}hydrate_all`);
+}
+
 export class SyncedState<T> {
 	name: string;
 	obj: T = $state({} as T);
+	version: number | null = null;
 	ready: boolean = $state(false);
 	initialized: Promise<void>;
 	#un_sub: UnlistenFn | undefined;
+	#un_sub_init: UnlistenFn | undefined;
+	#un_sub_removed: UnlistenFn | undefined;
+	#un_sub_batch: UnlistenFn | undefined;
+	#un_sub_patch: UnlistenFn | undefined;
+	#commands: string;
+	#seq: number | null = null;
+	// the origin #seq counts for, a restarted backend or another syncer starts over
+	#seqOrigin: string | null = null;
 
-	constructor(name: string, object?: T) {
+	constructor(name: string, object?: T, naming?: EventNaming) {
 		this.name = name;
 		if (object !== undefined) {
 			this.obj = object;
 		}
 
+		this.#commands = naming?.syncer ? `${naming.syncer}_` : '';
+		const prefix = naming?.prefix ?? '';
+		const update_event = `${prefix}${this.name}${naming?.suffix ?? '_update'}`;
+		// sent instead of an update for the value emit_state asks for when the backend has
+		// init_events set
+		const init_event = `${prefix}${this.name}_init`;
+
 		this.initialized = new Promise((resolve) => {
-			listen<T>(`${this.name}_update`, (event) => {
-				console.log(`DEBUG [SyncedStore]: ${this.name}_update event`, event.payload);
-				this.obj = event.payload;
+			const apply = (event: { event: string; payload: VersionedValue<T> }) => {
+				debug(`${event.event} event`, event.payload);
+				if (!this.#inOrder(event.payload)) {
+					return;
+				}
+				this.obj = event.payload.value;
+				this.version = event.payload.version;
 				if (!this.ready) {
 					this.ready = true;
 					resolve();
 				}
-			}).then((f) => {
-				this.#un_sub = f;
-				invoke('emit_state', { name: this.name });
+			};
+			Promise.all([
+				listen<VersionedValue<T>>(update_event, apply),
+				listen<VersionedValue<T>>(init_event, apply)
+			]).then(([un_sub, un_sub_init]) => {
+				this.#un_sub = un_sub;
+				this.#un_sub_init = un_sub_init;
+				invoke(`${this.#commands}emit_state`, { name: this.name });
 			});
 		});
+
+		listen(`${prefix}${this.name}_removed`, () => {
+			debug(`${this.name}_removed event`);
+			this.ready = false;
+		}).then((f) => {
+			this.#un_sub_removed = f;
+		});
+
+		listen<Record<string, VersionedValue<T>>>(`${prefix}batch_update`, (event) => {
+			if (!(this.name in event.payload)) {
+				return;
+			}
+			debug(`${this.name} batch_update event`, event.payload);
+			if (!this.#inOrder(event.payload[this.name])) {
+				return;
+			}
+			this.obj = event.payload[this.name].value;
+			this.version = event.payload[this.name].version;
+		}).then((f) => {
+			this.#un_sub_batch = f;
+		});
+
+		listen<VersionedValue<unknown>>(`${prefix}${this.name}_patch`, (event) => {
+			debug(`${this.name}_patch event`, event.payload);
+			if (!this.ready) {
+				// nothing to patch yet, ask for the whole value instead
+				invoke(`${this.#commands}emit_state`, { name: this.name });
+				return;
+			}
+			if (!this.#inOrder(event.payload)) {
+				return;
+			}
+			this.obj = mergePatch($state.snapshot(this.obj), event.payload.value) as T;
+			this.version = event.payload.version;
+		}).then((f) => {
+			this.#un_sub_patch = f;
+		});
+	}
+
+	// whether an event is newer than the last one applied, so one that arrives late doesn't
+	// overwrite a newer value. seqs only compare within one origin, the first event from a
+	// new origin is always applied, as are events without a seq
+	#inOrder(payload: VersionedValue<unknown>): boolean {
+		if (payload.seq === undefined) {
+			return true;
+		}
+		if (payload.origin !== this.#seqOrigin) {
+			this.#seqOrigin = payload.origin;
+			this.#seq = null;
+		}
+		if (this.#seq !== null && payload.seq <= this.#seq) {
+			console.warn(
+				`[SyncedStore]: ${this.name} - dropping out of order event`,
+				payload.seq,
+				this.#seq
+			);
+			return false;
+		}
+		this.#seq = payload.seq;
+		return true;
 	}
 
 	close() {
 		if (this.#un_sub) {
 			this.#un_sub();
 		}
+		if (this.#un_sub_init) {
+			this.#un_sub_init();
+		}
+		if (this.#un_sub_removed) {
+			this.#un_sub_removed();
+		}
+		if (this.#un_sub_batch) {
+			this.#un_sub_batch();
+		}
+		if (this.#un_sub_patch) {
+			this.#un_sub_patch();
+		}
 	}
 
 	async sync(): Promise<boolean> {
 		const val = $state.snapshot(this.obj);
-		console.log(`DEBUG [SyncedStore]: ${this.name} - syncing`, val);
-		return invoke('update_state', {
-			state: { version: null, name: this.name, value: JSON.stringify(val) }
+		debug(`${this.name} - syncing`, val);
+		return invoke(`${this.#commands}update_state`, {
+			state: { version: this.version, name: this.name, value: JSON.stringify(val), origin: null }
 		});
 	}
 }
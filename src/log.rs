@@ -0,0 +1,87 @@
+// the crate's logging macros: tracing's with the tracing feature, no-ops without it.
+// public so the exported macros can log through $crate::log
+#[cfg(feature = "tracing")]
+pub use tracing::{debug, error, info, warn};
+
+// takes the same fields and message as the tracing macros. nothing is evaluated, the
+// arguments are only referenced under if false so they don't turn into unused variables
+#[cfg(not(feature = "tracing"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __log_noop {
+    () => {
+        ()
+    };
+    ($name:tt = % $value:expr $(, $($rest:tt)*)?) => {{
+        if false {
+            let _ = &$value;
+        }
+        $crate::__log_noop!($($($rest)*)?)
+    }};
+    ($name:tt = ? $value:expr $(, $($rest:tt)*)?) => {{
+        if false {
+            let _ = &$value;
+        }
+        $crate::__log_noop!($($($rest)*)?)
+    }};
+    ($name:tt = $value:expr $(, $($rest:tt)*)?) => {{
+        if false {
+            let _ = &$value;
+        }
+        $crate::__log_noop!($($($rest)*)?)
+    }};
+    ($message:literal $(, $arg:expr)* $(,)?) => {{
+        if false {
+            $(let _ = &$arg;)*
+        }
+    }};
+    (% $name:ident $(, $($rest:tt)*)?) => {{
+        if false {
+            let _ = &$name;
+        }
+        $crate::__log_noop!($($($rest)*)?)
+    }};
+    (? $name:ident $(, $($rest:tt)*)?) => {{
+        if false {
+            let _ = &$name;
+        }
+        $crate::__log_noop!($($($rest)*)?)
+    }};
+    ($name:ident $(, $($rest:tt)*)?) => {{
+        if false {
+            let _ = &$name;
+        }
+        $crate::__log_noop!($($($rest)*)?)
+    }};
+}
+
+#[cfg(not(feature = "tracing"))]
+pub use crate::{__log_noop as debug, __log_noop as error, __log_noop as info, __log_noop as warn};
+
+// a span for an operation on a key, entered for as long as the returned guard lives, so the
+// logs for one key can be filtered with key=... . the key's type is recorded when it's known
+#[cfg(feature = "tracing")]
+macro_rules! key_span {
+    ($key:expr) => {
+        tracing::debug_span!("state", key = %$key).entered()
+    };
+    ($key:expr, $t:ty) => {
+        tracing::debug_span!("state", key = %$key, type = std::any::type_name::<$t>()).entered()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! key_span {
+    ($key:expr $(, $t:ty)?) => {{
+        if false {
+            let _ = &$key;
+        }
+        $crate::log::NoSpan
+    }};
+}
+
+// what key_span! returns without tracing
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoSpan;
+
+pub(crate) use key_span;
@@ -0,0 +1,28 @@
+use std::sync::Arc;
+
+use serde_json::Value;
+
+// what a middleware decides about an inbound update, after any changes it made to the value
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MiddlewareResult {
+    // hand the value on to the next middleware
+    Continue,
+    // accept the value as it is now, skipping the middleware registered after this one
+    Accept,
+    // refuse the update with a reason, the key keeps its current value
+    Reject(String),
+}
+
+pub(crate) type MiddlewareFn = Arc<dyn Fn(&str, &mut Value) -> MiddlewareResult + Send + Sync>;
+
+// run the chain over a value in registration order, returning the reason if one rejected it
+pub(crate) fn run(chain: &[MiddlewareFn], key: &str, value: &mut Value) -> Result<(), String> {
+    for middleware in chain {
+        match middleware(key, value) {
+            MiddlewareResult::Continue => {}
+            MiddlewareResult::Accept => break,
+            MiddlewareResult::Reject(reason) => return Err(reason),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+// values for keys with migrations are stored wrapped as {"__schema_version": n, "value": ...}.
+// a value without the wrapper was written before any migration existed, i.e. version 0
+const SCHEMA_VERSION_FIELD: &str = "__schema_version";
+const VALUE_FIELD: &str = "value";
+
+type MigrateFn = dyn Fn(Value) -> Value + Send + Sync;
+
+struct Migration {
+    from: u32,
+    to: u32,
+    f: Box<MigrateFn>,
+}
+
+// Migrations holds the registered upgrades for each key. a key's current schema version is the
+// highest version any of its migrations upgrades to, so keys without migrations stay at 0
+#[derive(Default)]
+pub(crate) struct Migrations {
+    by_key: HashMap<String, Vec<Migration>>,
+}
+
+impl Migrations {
+    pub(crate) fn register(&mut self, key: &str, from: u32, to: u32, f: Box<MigrateFn>) {
+        self.by_key
+            .entry(key.to_string())
+            .or_default()
+            .push(Migration { from, to, f });
+    }
+
+    pub(crate) fn schema_version(&self, key: &str) -> u32 {
+        self.by_key
+            .get(key)
+            .and_then(|migrations| migrations.iter().map(|m| m.to).max())
+            .unwrap_or(0)
+    }
+
+    // unwrap a value read from disk and run it through migrations until it's at the key's
+    // current schema version. on error returns the version no migration could move it past
+    pub(crate) fn upgrade(&self, key: &str, stored: Value) -> Result<Value, u32> {
+        let (mut version, mut value) = untag(stored);
+        let target = self.schema_version(key);
+
+        while version < target {
+            let migration = self
+                .by_key
+                .get(key)
+                .and_then(|migrations| migrations.iter().find(|m| m.from == version))
+                .ok_or(version)?;
+            value = (migration.f)(value);
+            version = migration.to;
+        }
+
+        // the value was written by a newer schema than this build knows about
+        if version != target {
+            return Err(version);
+        }
+        Ok(value)
+    }
+}
+
+// wrap a value with its schema version on the way to disk, left as is for version 0
+pub(crate) fn tag(version: u32, value: Value) -> Value {
+    if version == 0 {
+        return value;
+    }
+    serde_json::json!({ SCHEMA_VERSION_FIELD: version, VALUE_FIELD: value })
+}
+
+fn untag(stored: Value) -> (u32, Value) {
+    match stored {
+        Value::Object(mut map)
+            if map.len() == 2
+                && map.contains_key(VALUE_FIELD)
+                && map.get(SCHEMA_VERSION_FIELD).is_some_and(Value::is_u64) =>
+        {
+            let version = map[SCHEMA_VERSION_FIELD].as_u64().unwrap_or_default() as u32;
+            let value = map.remove(VALUE_FIELD).unwrap_or_default();
+            (version, value)
+        }
+        other => (0, other),
+    }
+}
@@ -0,0 +1,100 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+
+use crate::lock_recover;
+
+// called with the stored value for the key, downcast back to its type by the closure on_change builds
+pub(crate) type ChangeFn = Arc<dyn Fn(&dyn Any) + Send + Sync>;
+// called with the key and its new value serialized to JSON, for every key under a prefix
+pub(crate) type AnyChangeFn = Arc<dyn Fn(&str, &str) + Send + Sync>;
+
+// Observers holds the backend callbacks registered for state changes
+#[derive(Default)]
+pub(crate) struct Observers {
+    next_id: u64,
+    by_key: HashMap<String, Vec<(u64, ChangeFn)>>,
+    // on_any_change is registered with an empty prefix, which every key starts with
+    by_prefix: Vec<(u64, String, AnyChangeFn)>,
+}
+
+impl Observers {
+    pub(crate) fn add(&mut self, key: &str, f: ChangeFn) -> u64 {
+        self.next_id += 1;
+        self.by_key
+            .entry(key.to_string())
+            .or_default()
+            .push((self.next_id, f));
+        self.next_id
+    }
+
+    pub(crate) fn add_prefix(&mut self, prefix: &str, f: AnyChangeFn) -> u64 {
+        self.next_id += 1;
+        self.by_prefix.push((self.next_id, prefix.to_string(), f));
+        self.next_id
+    }
+
+    fn remove(&mut self, id: u64) {
+        self.by_prefix
+            .retain(|(callback_id, _, _)| *callback_id != id);
+        self.by_key.retain(|_, callbacks| {
+            callbacks.retain(|(callback_id, _)| *callback_id != id);
+            !callbacks.is_empty()
+        });
+    }
+
+    // whether a change to key has anything to notify
+    pub(crate) fn watches(&self, key: &str) -> bool {
+        self.by_key.contains_key(key)
+            || self
+                .by_prefix
+                .iter()
+                .any(|(_, prefix, _)| key.starts_with(prefix.as_str()))
+    }
+
+    // the callbacks to run for a change to key, in registration order. they're copied out so
+    // they can be called without holding the lock, letting a callback use the syncer freely
+    pub(crate) fn for_key(&self, key: &str) -> Vec<ChangeFn> {
+        self.by_key
+            .get(key)
+            .map(|callbacks| callbacks.iter().map(|(_, f)| f.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn for_prefixes_of(&self, key: &str) -> Vec<AnyChangeFn> {
+        self.by_prefix
+            .iter()
+            .filter(|(_, prefix, _)| key.starts_with(prefix.as_str()))
+            .map(|(_, _, f)| f.clone())
+            .collect()
+    }
+}
+
+// Subscription unregisters its callback when dropped, call detach to keep it registered
+// for as long as the syncer lives
+#[must_use = "the callback is unregistered as soon as the subscription is dropped"]
+pub struct Subscription {
+    id: u64,
+    observers: Weak<Mutex<Observers>>,
+}
+
+impl Subscription {
+    pub(crate) fn new(id: u64, observers: &Arc<Mutex<Observers>>) -> Self {
+        Subscription {
+            id,
+            observers: Arc::downgrade(observers),
+        }
+    }
+
+    pub fn detach(mut self) {
+        self.observers = Weak::new();
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(observers) = self.observers.upgrade() {
+            lock_recover(&observers).remove(self.id);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LockResult, RwLock, TryLockResult};
+use std::time::Duration;
+
+use tauri::{Runtime, Wry};
+
+use crate::log::{debug, key_span};
+use crate::{ItemGuard, ItemTrait, StateSyncer, lock_value, lock_value_timeout, try_lock_value};
+
+// OwnedItem is an Item holding its own syncer handle, so it's 'static and Send. changes
+// are synced when it drops, or earlier with commit
+pub struct OwnedItem<T: ItemTrait, R: Runtime = Wry> {
+    value: Arc<RwLock<T>>,
+    key: String,
+    syncer: StateSyncer<R>,
+    dirty: AtomicBool,
+}
+
+impl<T: ItemTrait, R: Runtime> OwnedItem<T, R> {
+    pub(crate) fn new(value: Arc<RwLock<T>>, key: &str, syncer: StateSyncer<R>) -> Self {
+        OwnedItem {
+            value,
+            key: key.to_string(),
+            syncer,
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn lock(&'_ self) -> LockResult<ItemGuard<'_, T>> {
+        lock_value(&self.syncer, &self.key, &self.value, &self.dirty)
+    }
+
+    pub fn try_lock(&'_ self) -> TryLockResult<ItemGuard<'_, T>> {
+        try_lock_value(&self.syncer, &self.key, &self.value, &self.dirty)
+    }
+
+    pub fn lock_timeout(&'_ self, timeout: Duration) -> TryLockResult<ItemGuard<'_, T>> {
+        lock_value_timeout(&self.syncer, &self.key, &self.value, &self.dirty, timeout)
+    }
+
+    pub fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    // emit and persist the changes made so far now rather than on drop. returns whether
+    // there was anything to sync
+    pub fn commit(&self) -> bool {
+        let _span = key_span!(self.key, T);
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            debug!("[OwnedItem] nothing to commit");
+            return false;
+        }
+        self.syncer.sync_value(&self.key, &self.value);
+        true
+    }
+}
+
+impl<T: ItemTrait, R: Runtime> Drop for OwnedItem<T, R> {
+    fn drop(&mut self) {
+        self.commit();
+    }
+}
@@ -0,0 +1,70 @@
+use serde_json::Value;
+
+// apply an RFC 7386 JSON Merge Patch: objects merge recursively, null removes a field and
+// anything else replaces the target outright
+pub(crate) fn merge(target: &mut Value, patch: &Value) {
+    let Value::Object(fields) = patch else {
+        *target = patch.clone();
+        return;
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let Value::Object(target_fields) = target else {
+        return;
+    };
+    for (name, value) in fields {
+        if value.is_null() {
+            target_fields.remove(name);
+        } else {
+            merge(
+                target_fields.entry(name.clone()).or_insert(Value::Null),
+                value,
+            );
+        }
+    }
+}
+
+// the merge patch that turns old into new, so only the changed fields need to be sent.
+// like any merge patch it can't set a field to null, a null in new removes the field instead
+pub(crate) fn diff(old: &Value, new: &Value) -> Value {
+    let (Value::Object(old_fields), Value::Object(new_fields)) = (old, new) else {
+        return new.clone();
+    };
+
+    let mut patch = serde_json::Map::new();
+    for name in old_fields.keys() {
+        if !new_fields.contains_key(name) {
+            patch.insert(name.clone(), Value::Null);
+        }
+    }
+    for (name, new_value) in new_fields {
+        match old_fields.get(name) {
+            Some(old_value) if old_value == new_value => {}
+            Some(old_value) => {
+                patch.insert(name.clone(), diff(old_value, new_value));
+            }
+            None => {
+                patch.insert(name.clone(), new_value.clone());
+            }
+        }
+    }
+    Value::Object(patch)
+}
+
+// a diff of two equal objects, nothing worth sending
+pub(crate) fn is_empty(patch: &Value) -> bool {
+    matches!(patch, Value::Object(fields) if fields.is_empty())
+}
+
+// whether merging value as a patch would lose a field set to null. arrays are replaced
+// outright, so nulls inside them survive
+pub(crate) fn has_null_field(value: &Value) -> bool {
+    let Value::Object(fields) = value else {
+        return false;
+    };
+    fields
+        .values()
+        .any(|field| field.is_null() || has_null_field(field))
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+use tauri::Runtime;
+use tauri_plugin_store::Store;
+
+use crate::disk::DiskFile;
+use crate::log::{debug, error};
+
+// serializes a value on the persist thread rather than the caller's, None skips the write
+pub(crate) type Encode = Box<dyn FnOnce() -> Option<Value> + Send>;
+
+enum PersistMsg {
+    Write(String, Encode),
+    Delete(String),
+    Clear,
+    Flush(mpsc::Sender<Result<(), tauri_plugin_store::Error>>),
+}
+
+// PersistWorker writes to disk on a background thread, each key at most once per
+// interval with the latest value winning
+pub(crate) struct PersistWorker {
+    tx: mpsc::Sender<PersistMsg>,
+}
+
+impl PersistWorker {
+    pub(crate) fn new<R: Runtime>(
+        store: Arc<Store<R>>,
+        file: DiskFile,
+        interval: Duration,
+    ) -> io::Result<Self> {
+        let (tx, rx) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("state-syncer-persist".to_owned())
+            .spawn(move || run(store, file, rx, interval))?;
+
+        Ok(PersistWorker { tx })
+    }
+
+    pub(crate) fn write(&self, key: &str, value: Encode) {
+        if self
+            .tx
+            .send(PersistMsg::Write(key.to_string(), value))
+            .is_err()
+        {
+            error!(key, "persist thread has stopped, dropping write");
+        }
+    }
+
+    pub(crate) fn delete(&self, key: &str) {
+        if self.tx.send(PersistMsg::Delete(key.to_string())).is_err() {
+            error!(key, "persist thread has stopped, dropping delete");
+        }
+    }
+
+    // drop anything pending along with everything already in the store
+    pub(crate) fn clear(&self) {
+        if self.tx.send(PersistMsg::Clear).is_err() {
+            error!("persist thread has stopped, dropping clear");
+        }
+    }
+
+    // write out anything pending and save the store, blocking until it's done
+    pub(crate) fn flush(&self) -> Result<(), tauri_plugin_store::Error> {
+        let (ack_tx, ack_rx) = mpsc::channel();
+        if self.tx.send(PersistMsg::Flush(ack_tx)).is_err() {
+            error!("persist thread has stopped, nothing to flush");
+            return Ok(());
+        }
+
+        // if the thread went away mid-flush it already wrote what it had on the way out
+        ack_rx.recv().unwrap_or(Ok(()))
+    }
+}
+
+fn run<R: Runtime>(
+    store: Arc<Store<R>>,
+    file: DiskFile,
+    rx: mpsc::Receiver<PersistMsg>,
+    interval: Duration,
+) {
+    let mut pending: HashMap<String, Encode> = HashMap::new();
+    let mut deadline: Option<Instant> = None;
+
+    loop {
+        let msg = match deadline {
+            Some(at) => rx.recv_timeout(at.saturating_duration_since(Instant::now())),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match msg {
+            Ok(PersistMsg::Write(key, value)) => {
+                pending.insert(key, value);
+                deadline.get_or_insert_with(|| Instant::now() + interval);
+            }
+            Ok(PersistMsg::Delete(key)) => {
+                pending.remove(&key);
+                store.delete(&key);
+            }
+            Ok(PersistMsg::Clear) => {
+                pending.clear();
+                store.clear();
+            }
+            Ok(PersistMsg::Flush(ack)) => {
+                deadline = None;
+                let _ = ack.send(write_pending(&store, &file, &mut pending));
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                deadline = None;
+                if let Err(err) = write_pending(&store, &file, &mut pending) {
+                    error!("failed to save state: {}", err);
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                // the syncer is gone, make sure the trailing writes still land
+                if let Err(err) = write_pending(&store, &file, &mut pending) {
+                    error!("failed to save state on shutdown: {}", err);
+                }
+                return;
+            }
+        }
+    }
+}
+
+fn write_pending<R: Runtime>(
+    store: &Store<R>,
+    file: &DiskFile,
+    pending: &mut HashMap<String, Encode>,
+) -> Result<(), tauri_plugin_store::Error> {
+    debug!(count = pending.len(), "writing pending state");
+    for (key, encode) in pending.drain() {
+        if let Some(value) = encode() {
+            store.set(key, value);
+        }
+    }
+
+    file.save(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Barrier;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use serde::{Deserialize, Serialize, Serializer};
+
+    use crate::StateSyncerConfig;
+    use crate::tests::{TempDir, mock_app, on_disk, read_store};
+
+    // whoever serializes a Gated waits here for the test to catch up
+    static GATE: Barrier = Barrier::new(2);
+
+    #[derive(Deserialize, Clone, Debug)]
+    struct Gated(i32);
+
+    impl Serialize for Gated {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            GATE.wait();
+            serializer.serialize_i32(self.0)
+        }
+    }
+
+    #[test]
+    fn async_persist_returns_before_the_write() {
+        let app = mock_app();
+        let dir = TempDir::new();
+        let syncer = on_disk(&app, &dir, StateSyncerConfig::builder().async_persist(true));
+
+        // a new key is only serialized for its disk write, which can't finish until the
+        // test reaches the gate after update has returned
+        let (done_tx, done_rx) = mpsc::channel();
+        let updater = syncer.clone();
+        thread::spawn(move || {
+            updater.update("gated", Gated(7), false);
+            done_tx.send(()).unwrap();
+        });
+        done_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("update waited for the disk write");
+
+        GATE.wait();
+        syncer.flush().unwrap();
+        assert_eq!(read_store(&dir)["gated"], 7);
+    }
+}
@@ -0,0 +1,142 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use tauri::{Runtime, Wry};
+
+use crate::{Item, ItemTrait, OwnedItem, ReadItem, StateError, StateSyncer};
+
+// ScopedSyncer prepends "{prefix}." to every key it hands to the syncer it was created
+// from, events carry the full key
+pub struct ScopedSyncer<'s, R: Runtime = Wry> {
+    syncer: &'s StateSyncer<R>,
+    prefix: String,
+}
+
+impl<'s, R: Runtime> ScopedSyncer<'s, R> {
+    pub(crate) fn new(syncer: &'s StateSyncer<R>, prefix: &str) -> Self {
+        ScopedSyncer {
+            syncer,
+            prefix: format!("{}.", prefix),
+        }
+    }
+
+    // the fully-qualified key the syncer stores key under
+    pub fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    // a scope nested under this one, e.g. scope("window").scope("main")
+    pub fn scope(&self, prefix: &str) -> ScopedSyncer<'s, R> {
+        ScopedSyncer::new(self.syncer, &self.key(prefix))
+    }
+
+    pub fn get<T: ItemTrait>(&self, key: &str) -> Item<'s, T, R> {
+        self.try_get(key).unwrap_or_else(|err| {
+            self.syncer.report(&err);
+            panic!("{}", err)
+        })
+    }
+
+    pub fn try_get<T: ItemTrait>(&self, key: &str) -> Result<Item<'s, T, R>, StateError> {
+        self.syncer.item(Cow::Owned(self.key(key)))
+    }
+
+    pub fn owned_handle<T: ItemTrait>(&self, key: &str) -> OwnedItem<T, R> {
+        self.syncer.owned_handle(&self.key(key))
+    }
+
+    pub fn try_owned_handle<T: ItemTrait>(&self, key: &str) -> Result<OwnedItem<T, R>, StateError> {
+        self.syncer.try_owned_handle(&self.key(key))
+    }
+
+    pub fn stage<T: ItemTrait>(&self, key: &str, initial: T) -> Item<'s, T, R> {
+        self.syncer.staged_item(Cow::Owned(self.key(key)), initial)
+    }
+
+    pub fn get_or_insert_with<T: ItemTrait, F: FnOnce() -> T>(
+        &self,
+        key: &str,
+        f: F,
+    ) -> Item<'s, T, R> {
+        self.try_get_or_insert_with(key, f).unwrap_or_else(|err| {
+            self.syncer.report(&err);
+            panic!("{}", err)
+        })
+    }
+
+    pub fn try_get_or_insert_with<T: ItemTrait, F: FnOnce() -> T>(
+        &self,
+        key: &str,
+        f: F,
+    ) -> Result<Item<'s, T, R>, StateError> {
+        self.syncer
+            .item_or_insert_with(Cow::Owned(self.key(key)), f)
+    }
+
+    pub fn get_ref<T: ItemTrait>(&self, key: &str) -> ReadItem<T> {
+        self.syncer.get_ref(&self.key(key))
+    }
+
+    pub fn try_get_ref<T: ItemTrait>(&self, key: &str) -> Result<ReadItem<T>, StateError> {
+        self.syncer.try_get_ref(&self.key(key))
+    }
+
+    pub fn set<T: ItemTrait>(&self, key: &str, value: T) -> Option<T> {
+        self.syncer.set(&self.key(key), value)
+    }
+
+    pub fn update<T: ItemTrait>(&self, key: &str, new_value: T, emit: bool) -> Option<T> {
+        self.syncer.update(&self.key(key), new_value, emit)
+    }
+
+    pub fn snapshot<T: ItemTrait>(&self, key: &str) -> T {
+        self.syncer.snapshot(&self.key(key))
+    }
+
+    pub fn try_snapshot<T: ItemTrait>(&self, key: &str) -> Option<T> {
+        self.syncer.try_snapshot(&self.key(key))
+    }
+
+    pub fn snapshot_checked<T: ItemTrait>(&self, key: &str) -> Result<T, StateError> {
+        self.syncer.snapshot_checked(&self.key(key))
+    }
+
+    pub fn snapshot_versioned<T: ItemTrait>(&self, key: &str) -> Option<(T, u128)> {
+        self.syncer.snapshot_versioned(&self.key(key))
+    }
+
+    // keys in the result are unprefixed, like the ones passed in
+    pub fn snapshot_many(&self, keys: &[&str]) -> HashMap<String, serde_json::Value> {
+        let full: Vec<String> = keys.iter().map(|key| self.key(key)).collect();
+        let full: Vec<&str> = full.iter().map(String::as_str).collect();
+        self.syncer
+            .snapshot_many(&full)
+            .into_iter()
+            .filter_map(|(key, value)| Some((key.strip_prefix(&self.prefix)?.to_string(), value)))
+            .collect()
+    }
+
+    pub fn with<T: ItemTrait, U, F: FnOnce(&T) -> U>(
+        &self,
+        key: &str,
+        f: F,
+    ) -> Result<U, StateError> {
+        self.syncer.with(&self.key(key), f)
+    }
+
+    pub fn read_copy<T: ItemTrait + Copy>(&self, key: &str) -> Result<T, StateError> {
+        self.syncer.read_copy(&self.key(key))
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.syncer.contains_key(&self.key(key))
+    }
+
+    pub fn type_name_of(&self, key: &str) -> Option<&'static str> {
+        self.syncer.type_name_of(&self.key(key))
+    }
+
+    pub fn remove(&self, key: &str) -> bool {
+        self.syncer.remove(&self.key(key))
+    }
+}
@@ -0,0 +1,116 @@
+#[cfg(any(test, feature = "test-util"))]
+use std::sync::{Arc, Mutex};
+
+#[cfg(any(test, feature = "test-util"))]
+use serde_json::Value;
+use tauri::{AppHandle, Emitter, EventTarget, Manager, Runtime};
+
+#[cfg(any(test, feature = "test-util"))]
+use crate::lock_recover;
+
+// EventSink is where a syncer sends its events: TauriSink to the app's windows, or any
+// other through StateSyncer::with_sink
+pub trait EventSink: Send + Sync {
+    // send an event to every window, or only the window with the given label. the payload
+    // is already serialized to JSON, so it's only serialized once however it's sent on
+    fn emit(&self, window: Option<&str>, name: &str, payload: &str) -> Result<(), tauri::Error>;
+
+    // whether a window with the label exists, for emit_to_window
+    fn has_window(&self, _label: &str) -> bool {
+        true
+    }
+
+    // whether there are any windows at all, change events are queued until there are
+    fn has_windows(&self) -> bool {
+        true
+    }
+}
+
+pub struct TauriSink<R: Runtime>(pub AppHandle<R>);
+
+impl<R: Runtime> EventSink for TauriSink<R> {
+    fn emit(&self, window: Option<&str>, name: &str, payload: &str) -> Result<(), tauri::Error> {
+        match window {
+            Some(label) => {
+                self.0
+                    .emit_str_to(EventTarget::labeled(label), name, payload.to_string())
+            }
+            None => self.0.emit_str(name, payload.to_string()),
+        }
+    }
+
+    fn has_window(&self, label: &str) -> bool {
+        self.0.get_webview_window(label).is_some()
+    }
+
+    fn has_windows(&self) -> bool {
+        !self.0.webview_windows().is_empty()
+    }
+}
+
+// drops every event
+pub struct NoopSink;
+
+impl EventSink for NoopSink {
+    fn emit(&self, _window: Option<&str>, _name: &str, _payload: &str) -> Result<(), tauri::Error> {
+        Ok(())
+    }
+}
+
+// one event a RecordingSink received, window is None for an event sent to every window
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedEvent {
+    pub name: String,
+    pub window: Option<String>,
+    pub payload: Value,
+}
+
+// keeps every event it receives, so a test can check what a syncer emitted. clones share the
+// same events, so keep one to read them after handing the other to StateSyncer::with_sink
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Clone, Default)]
+pub struct RecordingSink {
+    events: Arc<Mutex<Vec<RecordedEvent>>>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl RecordingSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // every event so far, oldest first
+    pub fn events(&self) -> Vec<RecordedEvent> {
+        lock_recover(&self.events).clone()
+    }
+
+    // the _init, _update, _patch and _removed events for a key, assuming the default
+    // event_prefix and event_suffix
+    pub fn events_for(&self, key: &str) -> Vec<RecordedEvent> {
+        let names =
+            ["_init", "_update", "_patch", "_removed"].map(|suffix| format!("{}{}", key, suffix));
+        lock_recover(&self.events)
+            .iter()
+            .filter(|event| names.contains(&event.name))
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        lock_recover(&self.events).clear();
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl EventSink for RecordingSink {
+    fn emit(&self, window: Option<&str>, name: &str, payload: &str) -> Result<(), tauri::Error> {
+        lock_recover(&self.events).push(RecordedEvent {
+            name: name.to_string(),
+            window: window.map(str::to_string),
+            // parsed back so tests can look into it, the syncer only sends valid JSON
+            payload: serde_json::from_str(payload).unwrap_or(Value::Null),
+        });
+        Ok(())
+    }
+}
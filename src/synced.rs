@@ -0,0 +1,26 @@
+use tauri::Runtime;
+
+use crate::{StateError, StateSyncer};
+
+// a struct whose fields are each synced as a key, see #[derive(SyncedState)] (the derive
+// feature). a field's key is its name unless set with #[synced(key = "...")]
+pub trait SyncedState {
+    // the key of each field, in declaration order
+    const KEYS: &'static [&'static str];
+
+    // load each key from disk, falling back to the field's value in self
+    fn register<R: Runtime>(self, syncer: &StateSyncer<R>);
+
+    // emit the key's current value, false if it isn't one of KEYS or isn't set
+    fn emit<R: Runtime>(syncer: &StateSyncer<R>, key: &str) -> bool;
+
+    // StateSyncer::update_typed_string with the type of the field the key is for, None if it
+    // isn't one of KEYS
+    fn update<R: Runtime>(
+        syncer: &StateSyncer<R>,
+        key: &str,
+        value: &str,
+        version: Option<u128>,
+        emit: bool,
+    ) -> Option<Result<(), StateError>>;
+}
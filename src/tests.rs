@@ -0,0 +1,1255 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tauri::test::MockRuntime;
+
+use crate::*;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub(crate) struct Counter {
+    pub(crate) count: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub(crate) struct Label {
+    pub(crate) text: String,
+}
+
+// a syncer without an app, along with the sink its events go to
+pub(crate) fn headless(cfg: StateSyncerConfig) -> (StateSyncer, RecordingSink) {
+    let sink = RecordingSink::new();
+    (StateSyncer::with_sink(cfg, sink.clone()), sink)
+}
+
+// a directory of its own under the system temp dir, removed when it's dropped
+pub(crate) struct TempDir(pub(crate) PathBuf);
+
+impl TempDir {
+    pub(crate) fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "synced-store-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+pub(crate) fn mock_app() -> tauri::App<MockRuntime> {
+    tauri::test::mock_builder()
+        .plugin(tauri_plugin_store::Builder::new().build())
+        .build(tauri::test::mock_context(tauri::test::noop_assets()))
+        .unwrap()
+}
+
+// a syncer keeping its store file in dir, persisting every key by default
+pub(crate) fn on_disk(
+    app: &tauri::App<MockRuntime>,
+    dir: &TempDir,
+    cfg: StateSyncerConfigBuilder,
+) -> StateSyncer<MockRuntime> {
+    let cfg = cfg.directory(&dir.0).default_persist(true).build();
+    StateSyncer::new(cfg, app.handle().clone())
+}
+
+// the store file on_disk writes, parsed
+pub(crate) fn read_store(dir: &TempDir) -> serde_json::Value {
+    serde_json::from_slice(&std::fs::read(dir.0.join("state.json")).unwrap()).unwrap()
+}
+
+#[test]
+fn removing_a_key_drops_its_serializers() {
+    let (syncer, _) = headless(Default::default());
+    let registered = || lock_recover(&syncer.serializers).len();
+
+    syncer.set("counter", Counter::default());
+    assert_eq!(registered(), 1);
+    assert!(syncer.remove("counter"));
+    assert_eq!(registered(), 0);
+
+    // set again, this time with another type
+    syncer.set("counter", Label::default());
+    assert_eq!(registered(), 1);
+    assert_eq!(
+        syncer.type_name_of("counter"),
+        Some(std::any::type_name::<Label>())
+    );
+    assert!(syncer.remove("counter"));
+    assert_eq!(registered(), 0);
+}
+
+#[test]
+fn keys_lists_every_set_key() {
+    let (syncer, _) = headless(Default::default());
+    syncer.set("first", Counter::default());
+    syncer.set("second", Label::default());
+    syncer.set("third", Counter { count: 3 });
+
+    let mut keys = syncer.keys();
+    keys.sort();
+    assert_eq!(keys, ["first", "second", "third"]);
+    assert!(syncer.contains_key("second"));
+    assert!(!syncer.contains_key("fourth"));
+}
+
+#[test]
+fn updates_are_emitted_with_the_custom_suffix() {
+    let cfg = StateSyncerConfig::builder()
+        .event_prefix("app:")
+        .event_suffix(":changed")
+        .build();
+    let (syncer, sink) = headless(cfg);
+    syncer.set("counter", Counter::default());
+    syncer.update("counter", Counter { count: 2 }, true);
+
+    let events = sink.events();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].name, "app:counter:changed");
+    assert_eq!(
+        events[0].payload["value"],
+        serde_json::json!({ "count": 2 })
+    );
+    // events_for only knows the default names
+    assert!(sink.events_for("counter").is_empty());
+}
+
+mod events {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, specta::Type, Clone, Debug, Default)]
+    pub(crate) struct Volume {
+        pub(crate) level: u8,
+    }
+
+    crate::state_events!(prefix = "app:", suffix = ":changed"; Volume = "volume");
+
+    #[test]
+    fn state_events_are_named_like_the_events_sent() {
+        use tauri_specta::Event;
+        let _ = state_events::<MockRuntime>(tauri_specta::Builder::new());
+
+        let cfg = || {
+            StateSyncerConfig::builder()
+                .event_prefix("app:")
+                .event_suffix(":changed")
+        };
+        let (syncer, sink) = headless(cfg().init_events(true).build());
+        syncer.set("volume", Volume::default());
+        syncer.update("volume", Volume { level: 1 }, true);
+        let batch = vec![("volume".to_string(), serde_json::json!({ "level": 2 }))];
+        syncer.update_batch(batch, true).unwrap();
+        syncer.remove("volume");
+
+        let (patched, patches) = headless(cfg().emit_mode(EmitMode::Patch).build());
+        patched.set("volume", Volume::default());
+        patched.update("volume", Volume { level: 1 }, true);
+        patched.update("volume", Volume { level: 2 }, true);
+
+        let names: Vec<_> = [sink, patches]
+            .iter()
+            .flat_map(|sink| sink.events().into_iter().map(|event| event.name))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                VolumeInit::NAME,
+                VolumeUpdate::NAME,
+                BatchUpdate::NAME,
+                VolumeRemoved::NAME,
+                VolumeUpdate::NAME,
+                VolumePatch::NAME,
+            ]
+        );
+    }
+}
+
+#[test]
+fn update_if_changed_emits_once_for_the_same_value() {
+    let (syncer, sink) = headless(Default::default());
+    syncer.set("counter", Counter::default());
+
+    assert!(syncer.update_if_changed("counter", Counter { count: 1 }, true));
+    assert!(!syncer.update_if_changed("counter", Counter { count: 1 }, true));
+    assert_eq!(sink.events_for("counter").len(), 1);
+}
+
+#[test]
+fn set_emits_init_and_update_emits_update_with_init_events() {
+    let (syncer, sink) = headless(StateSyncerConfig::builder().init_events(true).build());
+    syncer.set("counter", Counter { count: 1 });
+    syncer.update("counter", Counter { count: 2 }, true);
+
+    let events = sink.events_for("counter");
+    let names: Vec<_> = events.iter().map(|event| event.name.as_str()).collect();
+    assert_eq!(names, ["counter_init", "counter_update"]);
+    assert_eq!(events[0].payload["value"]["count"], 1);
+    assert_eq!(events[1].payload["value"]["count"], 2);
+
+    // off by default, set emits nothing
+    let (syncer, sink) = headless(Default::default());
+    syncer.set("counter", Counter { count: 1 });
+    assert!(sink.events().is_empty());
+}
+
+#[test]
+fn try_snapshot_is_none_for_a_missing_or_mistyped_key() {
+    let (syncer, _) = headless(Default::default());
+    syncer.set("counter", Counter { count: 1 });
+
+    assert_eq!(
+        syncer.try_snapshot::<Counter>("counter"),
+        Some(Counter { count: 1 })
+    );
+    assert_eq!(syncer.try_snapshot::<Counter>("missing"), None);
+    assert_eq!(syncer.try_snapshot::<Label>("counter"), None);
+    assert!(matches!(
+        syncer.snapshot_checked::<Label>("counter"),
+        Err(StateError::TypeMismatch(_))
+    ));
+}
+
+#[test]
+fn snapshot_recovers_a_poisoned_key() {
+    let (syncer, _) = headless(Default::default());
+    syncer.set("counter", Counter { count: 4 });
+
+    // panic while the value is write locked, poisoning it
+    let panicked = std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                let _ = syncer.update_with("counter", |_: &mut Counter| panic!("boom"));
+            })
+            .join()
+            .is_err()
+    });
+    assert!(panicked);
+    assert!(matches!(
+        syncer.snapshot_checked::<Counter>("counter"),
+        Err(StateError::Poisoned(_))
+    ));
+    assert_eq!(syncer.snapshot::<Counter>("counter"), Counter { count: 4 });
+    assert_eq!(
+        syncer.try_snapshot::<Counter>("counter"),
+        Some(Counter { count: 4 })
+    );
+}
+
+#[test]
+fn ephemeral_keys_stay_off_disk() {
+    let app = mock_app();
+    let dir = TempDir::new();
+    let syncer = on_disk(&app, &dir, StateSyncerConfig::builder());
+    syncer.set("counter", Counter::default());
+    syncer.set_ephemeral("session", Label::default());
+    syncer.update(
+        "session",
+        Label {
+            text: "token".to_string(),
+        },
+        false,
+    );
+    syncer.update("counter", Counter { count: 1 }, false);
+    syncer.flush().unwrap();
+
+    let saved = read_store(&dir);
+    assert_eq!(saved["counter"]["count"], 1);
+    assert!(saved.get("session").is_none());
+}
+
+#[test]
+fn concurrent_update_and_remove_of_one_key() {
+    let (syncer, _) = headless(Default::default());
+    std::thread::scope(|scope| {
+        for thread in 0..4 {
+            let syncer = &syncer;
+            scope.spawn(move || {
+                for count in 0..500 {
+                    if thread % 2 == 0 {
+                        syncer.update("counter", Counter { count }, true);
+                    } else {
+                        syncer.remove("counter");
+                    }
+                }
+            });
+        }
+    });
+
+    // whichever ran last, the key is either gone or holds a whole Counter
+    if syncer.contains_key("counter") {
+        assert!(syncer.try_snapshot::<Counter>("counter").is_some());
+    }
+}
+
+#[test]
+fn concurrent_sets_leave_the_latest_value_on_disk() {
+    let app = mock_app();
+    let dir = TempDir::new();
+    let syncer = on_disk(
+        &app,
+        &dir,
+        StateSyncerConfig::builder().write_ahead_log(true),
+    );
+    std::thread::scope(|scope| {
+        for thread in 0..4 {
+            let syncer = &syncer;
+            scope.spawn(move || {
+                for count in 0..100 {
+                    if count % 2 == 0 {
+                        syncer.set("counter", Counter { count: thread });
+                    } else {
+                        syncer.update("counter", Counter { count: thread }, false);
+                    }
+                }
+            });
+        }
+    });
+    syncer.flush().unwrap();
+
+    // the disk write made last is the change made last, whichever thread got there first
+    let count = syncer.snapshot::<Counter>("counter").count;
+    assert_eq!(read_store(&dir)["counter"]["count"], count);
+}
+
+#[test]
+fn get_or_insert_with_stores_one_value_when_callers_race() {
+    let (syncer, _) = headless(Default::default());
+    let barrier = std::sync::Barrier::new(4);
+    let seen: Vec<i32> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..4)
+            .map(|thread| {
+                let (syncer, barrier) = (&syncer, &barrier);
+                scope.spawn(move || {
+                    barrier.wait();
+                    let item = syncer.get_or_insert_with("counter", || Counter { count: thread });
+                    item.lock().unwrap().count
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    // every caller got a handle to the one value that was stored
+    let stored = syncer.snapshot::<Counter>("counter").count;
+    assert!(seen.iter().all(|count| *count == stored));
+
+    assert!(matches!(
+        syncer.try_get_or_insert_with("counter", Label::default),
+        Err(StateError::TypeMismatch(_))
+    ));
+}
+
+#[test]
+fn transaction_with_an_invalid_change_stores_nothing() {
+    let (syncer, sink) = headless(Default::default());
+    syncer.set("first", Counter::default());
+    syncer.set("second", Counter::default());
+    syncer.set("volume", Counter { count: 50 });
+    syncer.set_validator("volume", |volume: &Counter| {
+        if (0..=100).contains(&volume.count) {
+            Ok(())
+        } else {
+            Err("volume out of range".to_string())
+        }
+    });
+
+    let result = syncer.transaction(|txn| {
+        txn.update("first", Counter { count: 1 });
+        txn.update("second", Counter { count: 2 });
+        txn.update("volume", Counter { count: 150 });
+        Ok(())
+    });
+    assert!(matches!(result, Err(StateError::Invalid(key, _)) if key == "volume"));
+    assert_eq!(syncer.snapshot::<Counter>("first").count, 0);
+    assert_eq!(syncer.snapshot::<Counter>("second").count, 0);
+    assert_eq!(syncer.snapshot::<Counter>("volume").count, 50);
+    assert!(sink.events().is_empty());
+}
+
+#[test]
+fn update_batch_with_a_key_that_changed_underneath_stores_nothing() {
+    let (syncer, sink) = headless(Default::default());
+    syncer.set("first", Counter { count: 1 });
+    syncer.set("second", Counter { count: 1 });
+    let batch = || {
+        vec![
+            ("first".to_string(), serde_json::json!({ "count": 2 })),
+            ("second".to_string(), serde_json::json!({ "count": 2 })),
+        ]
+    };
+
+    // as a set of another type or a remove leaves it between taking the locks
+    let label: Arc<dyn Any + Send + Sync> = Arc::new(RwLock::new(Label::default()));
+    write_recover(&syncer.data).insert("second".to_string(), label);
+    assert!(matches!(
+        syncer.update_batch(batch(), true),
+        Err(StateError::TypeMismatch(key)) if key == "second"
+    ));
+    write_recover(&syncer.data).remove("second");
+    assert!(matches!(
+        syncer.update_batch(batch(), true),
+        Err(StateError::KeyNotFound(key)) if key == "second"
+    ));
+
+    assert_eq!(syncer.snapshot::<Counter>("first"), Counter { count: 1 });
+    assert!(sink.events().is_empty());
+}
+
+#[test]
+fn import_with_a_key_holding_another_type_stores_nothing() {
+    let app = mock_app();
+    let dir = TempDir::new();
+    let syncer = on_disk(&app, &dir, StateSyncerConfig::builder());
+    syncer.set("first", Counter { count: 1 });
+    syncer.set("second", Counter { count: 1 });
+    syncer.flush().unwrap();
+    let label: Arc<dyn Any + Send + Sync> = Arc::new(RwLock::new(Label::default()));
+    write_recover(&syncer.data).insert("second".to_string(), label);
+
+    let mut map = serde_json::Map::new();
+    map.insert("first".to_string(), serde_json::json!({ "count": 2 }));
+    map.insert("second".to_string(), serde_json::json!({ "count": 2 }));
+    assert!(matches!(
+        syncer.import(map, true),
+        Err(StateError::TypeMismatch(key)) if key == "second"
+    ));
+
+    // memory and disk still agree
+    assert_eq!(syncer.snapshot::<Counter>("first"), Counter { count: 1 });
+    assert_eq!(read_store(&dir)["first"]["count"], 1);
+}
+
+#[test]
+fn update_batch_marks_its_keys_used() {
+    let (syncer, _) = headless(StateSyncerConfig::builder().max_keys(2).build());
+    syncer.set("first", Counter::default());
+    syncer.set("second", Counter::default());
+    let batch = vec![("first".to_string(), serde_json::json!({ "count": 1 }))];
+    syncer.update_batch(batch, false).unwrap();
+
+    syncer.set("third", Counter::default());
+    assert!(syncer.contains_key("first"));
+    assert!(!syncer.contains_key("second"));
+}
+
+#[test]
+fn patch_expires_touches_and_bumps_like_update() {
+    let (syncer, _) = headless(StateSyncerConfig::builder().max_keys(2).build());
+    syncer
+        .set_with_ttl("session", Counter::default(), Duration::ZERO)
+        .unwrap();
+    assert!(matches!(
+        syncer.patch("session", serde_json::json!({ "count": 1 }), false),
+        Err(StateError::KeyNotFound(_))
+    ));
+    assert!(!syncer.contains_key("session"));
+
+    syncer.set("first", Counter::default());
+    syncer.set("second", Counter::default());
+    let (_, before) = syncer.snapshot_versioned::<Counter>("first").unwrap();
+    syncer
+        .patch("first", serde_json::json!({ "count": 1 }), false)
+        .unwrap();
+    let (value, after) = syncer.snapshot_versioned::<Counter>("first").unwrap();
+    assert_eq!(value, Counter { count: 1 });
+    assert!(after > before);
+
+    // the patch counts as a use, so the next key evicts second instead
+    syncer.set("third", Counter::default());
+    assert!(syncer.contains_key("first"));
+    assert!(!syncer.contains_key("second"));
+}
+
+#[test]
+fn patch_mode_skips_empty_diffs_and_sends_nulls_whole() {
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    struct Profile {
+        name: String,
+        nickname: Option<String>,
+    }
+    let profile = |nickname: Option<&str>| Profile {
+        name: "ada".to_string(),
+        nickname: nickname.map(str::to_string),
+    };
+
+    let cfg = StateSyncerConfig::builder()
+        .emit_mode(EmitMode::Patch)
+        .build();
+    let (syncer, sink) = headless(cfg);
+    syncer.set("profile", profile(Some("a")));
+    syncer.update("profile", profile(Some("b")), true);
+    syncer.update("profile", profile(Some("c")), true);
+    syncer.update("profile", profile(Some("c")), true);
+    syncer.update("profile", profile(None), true);
+
+    let names: Vec<_> = sink.events().into_iter().map(|event| event.name).collect();
+    assert_eq!(names, ["profile_update", "profile_patch", "profile_update"]);
+    let last = sink.events().pop().unwrap();
+    assert!(last.payload["value"]["nickname"].is_null());
+}
+
+#[test]
+fn corrupt_store_file_is_never_overwritten() {
+    let app = mock_app();
+    let dir = TempDir::new();
+    let path = dir.0.join("state.json");
+    std::fs::write(&path, b"{\"counter\": {\"cou").unwrap();
+
+    // the syncer falls back to memory rather than saving over what's left of the file
+    let syncer = on_disk(&app, &dir, StateSyncerConfig::builder());
+    syncer.set("counter", Counter { count: 1 });
+    let _ = syncer.flush();
+    assert_eq!(std::fs::read(&path).unwrap(), b"{\"counter\": {\"cou");
+}
+
+#[test]
+fn corrupt_store_file_is_restored_from_a_backup() {
+    let dir = TempDir::new();
+    let path = dir.0.join("state.json");
+    {
+        let app = mock_app();
+        let syncer = on_disk(&app, &dir, StateSyncerConfig::builder().backups(2));
+        syncer.set("counter", Counter { count: 1 });
+        syncer.flush().unwrap();
+        syncer.update("counter", Counter { count: 2 }, false);
+        syncer.flush().unwrap();
+    }
+    // as if the app died halfway through the last write
+    std::fs::write(&path, b"{\"counter\": {\"cou").unwrap();
+
+    let app = mock_app();
+    let syncer = on_disk(&app, &dir, StateSyncerConfig::builder().backups(2));
+    assert_eq!(syncer.load::<Counter>("counter").count, 1);
+    // the broken file is set aside, not deleted
+    assert_eq!(
+        std::fs::read(dir.0.join("state.json.corrupt")).unwrap(),
+        b"{\"counter\": {\"cou"
+    );
+}
+
+#[test]
+fn emit_throttle_coalesces_a_burst_and_keeps_the_last_value() {
+    let cfg = StateSyncerConfig::builder()
+        .emit_throttle(Duration::from_millis(50))
+        .build();
+    let (syncer, sink) = headless(cfg);
+    syncer.set("pointer", Counter::default());
+    // about 200ms of updates, so a handful of intervals
+    for count in 1..=200 {
+        syncer.update("pointer", Counter { count }, true);
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    let last_count = || {
+        sink.events_for("pointer")
+            .last()
+            .map(|event| event.payload["value"]["count"].clone())
+    };
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while last_count() != Some(200.into()) {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "the final value was never emitted"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let emitted = sink.events_for("pointer").len();
+    assert!((2..=20).contains(&emitted), "{} events", emitted);
+}
+
+#[test]
+fn validators_reject_out_of_range_values_on_every_write() {
+    let (syncer, sink) = headless(Default::default());
+    syncer.set("volume", Counter { count: 50 });
+    syncer.set("level", 5_i32);
+    syncer.set_validator("volume", |volume: &Counter| {
+        if (0..=100).contains(&volume.count) {
+            Ok(())
+        } else {
+            Err("volume out of range".to_string())
+        }
+    });
+    syncer.set_validator("level", |level: &i32| {
+        if (0..=10).contains(level) {
+            Ok(())
+        } else {
+            Err("level out of range".to_string())
+        }
+    });
+    let invalid = |result: Result<(), StateError>| matches!(result, Err(StateError::Invalid(..)));
+
+    assert!(invalid(
+        syncer
+            .update_with("volume", |volume: &mut Counter| volume.count = 150)
+            .map(drop)
+    ));
+    assert!(invalid(syncer.fetch_add("level", 20).map(drop)));
+    assert!(invalid(syncer.fetch_sub("level", 20).map(drop)));
+    assert!(!syncer.compare_and_swap("volume", &Counter { count: 50 }, Counter { count: -1 }));
+    assert!(invalid(syncer.patch(
+        "volume",
+        serde_json::json!({ "count": 101 }),
+        true
+    )));
+    assert!(invalid(syncer.update_batch(
+        vec![("volume".to_string(), serde_json::json!({ "count": 200 }))],
+        true
+    )));
+    let mut import = serde_json::Map::new();
+    import.insert("level".to_string(), serde_json::json!(11));
+    assert!(invalid(syncer.import(import, true)));
+    {
+        let item = syncer.get::<Counter>("volume");
+        item.lock().unwrap().count = 500;
+    }
+
+    assert_eq!(syncer.snapshot::<Counter>("volume").count, 50);
+    assert_eq!(syncer.snapshot::<i32>("level"), 5);
+    assert!(sink.events().is_empty());
+
+    // values in range still go through
+    assert_eq!(syncer.fetch_add("level", 2).unwrap(), 7);
+}
+
+#[test]
+fn only_one_update_against_a_version_gets_in() {
+    let (syncer, _) = headless(Default::default());
+    syncer.set("counter", Counter::default());
+    let (_, version) = syncer.snapshot_versioned::<Counter>("counter").unwrap();
+
+    let stored = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        for count in 1..=8 {
+            let (syncer, stored) = (&syncer, &stored);
+            scope.spawn(move || {
+                match syncer.try_update_versioned(
+                    "counter",
+                    Counter { count },
+                    Some(version),
+                    false,
+                ) {
+                    Ok(_) => {
+                        stored.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(err) => assert!(matches!(err, StateError::Stale(_))),
+                }
+            });
+        }
+    });
+    assert_eq!(stored.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn missing_keys_are_never_marked_used() {
+    let (syncer, _) = headless(StateSyncerConfig::builder().max_keys(10).build());
+    syncer.set("counter", Counter::default());
+
+    assert!(syncer.try_snapshot::<Counter>("missing").is_none());
+    let values = syncer.snapshot_many(&["counter", "also_missing"]);
+    assert_eq!(values.len(), 1);
+    let untracked = lock_recover(&syncer.recency).least_recent(|key| key != "counter");
+    assert_eq!(untracked, None);
+}
+
+#[test]
+fn pretty_store_file_is_indented_and_sorted() {
+    let app = mock_app();
+    let dir = TempDir::new();
+    let syncer = on_disk(&app, &dir, StateSyncerConfig::builder().pretty(true));
+    for key in ["zebra", "apple", "mango"] {
+        syncer.set(key, Counter::default());
+    }
+    syncer.flush().unwrap();
+
+    let text = std::fs::read_to_string(dir.0.join("state.json")).unwrap();
+    assert!(text.lines().count() > 3, "not indented: {}", text);
+    let positions: Vec<_> = ["\"apple\"", "\"mango\"", "\"zebra\""]
+        .iter()
+        .map(|key| text.find(key).unwrap())
+        .collect();
+    assert!(positions.is_sorted(), "keys out of order: {}", text);
+}
+
+#[test]
+fn changes_queued_without_windows_go_out_with_the_next_emit() {
+    // a RecordingSink whose windows can be closed and opened again
+    #[derive(Clone)]
+    struct Windows(RecordingSink, Arc<AtomicBool>);
+
+    impl EventSink for Windows {
+        fn emit(
+            &self,
+            window: Option<&str>,
+            name: &str,
+            payload: &str,
+        ) -> Result<(), tauri::Error> {
+            self.0.emit(window, name, payload)
+        }
+
+        fn has_windows(&self) -> bool {
+            self.1.load(Ordering::Relaxed)
+        }
+    }
+
+    let sink = Windows(RecordingSink::new(), Arc::new(AtomicBool::new(false)));
+    let syncer: StateSyncer = StateSyncer::with_sink(Default::default(), sink.clone());
+    syncer.set("first", Counter::default());
+    syncer.set("second", Counter::default());
+    syncer.update("first", Counter { count: 1 }, true);
+    assert!(sink.0.events().is_empty());
+
+    // no on_run_event, the next change brings the queued one along
+    sink.1.store(true, Ordering::Relaxed);
+    syncer.update("second", Counter { count: 2 }, true);
+    let first = sink.0.events_for("first");
+    assert_eq!(first.len(), 1);
+    assert_eq!(first[0].payload["value"]["count"], 1);
+    assert_eq!(sink.0.events_for("second").len(), 1);
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn compression_shrinks_a_large_encrypted_store_and_loads_it_back() {
+    let key = EncryptionConfig::from_key([7; 32]);
+    let rows: Vec<Label> = (0..2000)
+        .map(|row| Label {
+            text: format!("row {} of a fairly repetitive table", row % 10),
+        })
+        .collect();
+    let saved_size = |compressed: bool| {
+        let app = mock_app();
+        let dir = TempDir::new();
+        let mut cfg = StateSyncerConfig::builder().encryption(key.clone());
+        if compressed {
+            cfg = cfg.compression(Compression::Gzip);
+        }
+        let syncer = on_disk(&app, &dir, cfg);
+        syncer.set("rows", rows.clone());
+        syncer.flush().unwrap();
+        let size = std::fs::metadata(dir.0.join("state.json")).unwrap().len();
+        (size, dir)
+    };
+
+    let (plain, _) = saved_size(false);
+    let (compressed, dir) = saved_size(true);
+    assert!(
+        compressed * 4 < plain,
+        "{} bytes, {} uncompressed",
+        compressed,
+        plain
+    );
+
+    let app = mock_app();
+    let cfg = StateSyncerConfig::builder()
+        .encryption(key)
+        .compression(Compression::Gzip);
+    let syncer = on_disk(&app, &dir, cfg);
+    assert_eq!(syncer.load::<Vec<Label>>("rows"), rows);
+}
+
+#[test]
+fn set_and_update_return_the_previous_value() {
+    let (syncer, sink) = headless(Default::default());
+    assert_eq!(syncer.set("counter", Counter { count: 1 }), None);
+    assert_eq!(
+        syncer.set("counter", Counter { count: 2 }),
+        Some(Counter { count: 1 })
+    );
+    assert_eq!(
+        syncer.update("counter", Counter { count: 3 }, true),
+        Some(Counter { count: 2 })
+    );
+    assert_eq!(
+        syncer
+            .try_update("counter", Counter { count: 4 }, true)
+            .unwrap(),
+        Some(Counter { count: 3 })
+    );
+    // the event carries the new value, not the one handed back
+    let events = sink.events_for("counter");
+    assert_eq!(events.last().unwrap().payload["value"]["count"], 4);
+
+    // an update that sets a missing key had nothing before it
+    assert_eq!(syncer.update("fresh", Counter::default(), true), None);
+}
+
+#[test]
+fn snapshot_many_never_sees_half_of_a_change() {
+    let (syncer, _) = headless(Default::default());
+    syncer.set("left", Counter::default());
+    syncer.set("right", Counter::default());
+
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            // held from the start, so the writes don't wait on the map like a lookup would
+            let (left, right) = (
+                syncer.get::<Counter>("left"),
+                syncer.get::<Counter>("right"),
+            );
+            for count in 1..=20_000 {
+                // both keys change together, locked in the order snapshot_many locks them
+                let mut left_guard = left.lock().unwrap();
+                let mut right_guard = right.lock().unwrap();
+                left_guard.count = count;
+                right_guard.count = count;
+            }
+        });
+        for _ in 0..20_000 {
+            let values = syncer.snapshot_many(&["right", "left"]);
+            assert_eq!(values["left"], values["right"]);
+        }
+    });
+}
+
+#[test]
+fn write_ahead_log_replays_changes_the_store_file_missed() {
+    let dir = TempDir::new();
+    let cfg = || StateSyncerConfig::builder().write_ahead_log(true);
+    let log = dir.0.join("state.json.wal");
+
+    let app = mock_app();
+    let syncer = on_disk(&app, &dir, cfg());
+    syncer.set("counter", Counter { count: 1 });
+    syncer.flush().unwrap();
+    // the store file has caught up with everything logged
+    assert!(!log.exists());
+
+    syncer.update("counter", Counter { count: 2 }, false);
+    syncer.set("label", Label::default());
+    // a crash before the next flush, nothing gets saved on the way out
+    std::mem::forget(syncer);
+    assert_eq!(read_store(&dir)["counter"]["count"], 1);
+    assert!(log.exists());
+
+    let app = mock_app();
+    let syncer = on_disk(&app, &dir, cfg());
+    assert_eq!(syncer.load::<Counter>("counter"), Counter { count: 2 });
+    assert_eq!(syncer.load::<Label>("label"), Label::default());
+    syncer.flush().unwrap();
+    assert_eq!(read_store(&dir)["counter"]["count"], 2);
+    assert!(!log.exists());
+}
+
+#[test]
+fn migrations_upgrade_old_values_through_every_version() {
+    let dir = TempDir::new();
+    std::fs::write(
+        dir.0.join("state.json"),
+        r#"{ "counter": { "clicks": 3 } }"#,
+    )
+    .unwrap();
+
+    let app = mock_app();
+    let syncer = on_disk(&app, &dir, StateSyncerConfig::builder());
+    // registered in any order, load runs them from the version on disk up
+    syncer.migrate(
+        "counter",
+        1,
+        2,
+        |value| serde_json::json!({ "count": value["total"] }),
+    );
+    syncer.migrate(
+        "counter",
+        0,
+        1,
+        |value| serde_json::json!({ "total": value["clicks"] }),
+    );
+    assert_eq!(syncer.load::<Counter>("counter"), Counter { count: 3 });
+
+    // saved tagged with the version it's at now
+    syncer.flush().unwrap();
+    let saved = read_store(&dir);
+    assert_eq!(saved["counter"]["__schema_version"], 2);
+    assert_eq!(saved["counter"]["value"]["count"], 3);
+}
+
+#[test]
+fn value_from_a_newer_schema_needs_a_migration() {
+    let dir = TempDir::new();
+    let stored = r#"{ "counter": { "__schema_version": 5, "value": { "count": 3 } } }"#;
+    std::fs::write(dir.0.join("state.json"), stored).unwrap();
+
+    let app = mock_app();
+    let syncer = on_disk(&app, &dir, StateSyncerConfig::builder());
+    syncer.migrate("counter", 0, 1, |value| value);
+    assert!(matches!(
+        syncer.try_load::<Counter>("counter"),
+        Err(StateError::MigrationRequired(key, 5)) if key == "counter"
+    ));
+}
+
+#[test]
+fn undo_and_redo_step_through_history_and_a_new_change_drops_the_redo() {
+    let (syncer, _) = headless(StateSyncerConfig::builder().history_depth(10).build());
+    syncer.set("counter", Counter { count: 1 });
+    syncer.update("counter", Counter { count: 2 }, true);
+    syncer.update("counter", Counter { count: 3 }, true);
+
+    let undo = || syncer.undo::<Counter>("counter").unwrap();
+    let redo = || syncer.redo::<Counter>("counter").unwrap();
+    assert_eq!(undo(), Some(Counter { count: 2 }));
+    assert_eq!(undo(), Some(Counter { count: 1 }));
+    assert_eq!(undo(), None);
+    assert_eq!(redo(), Some(Counter { count: 2 }));
+    assert_eq!(syncer.snapshot::<Counter>("counter"), Counter { count: 2 });
+
+    // a change made after an undo replaces what could have been redone
+    syncer.update("counter", Counter { count: 5 }, true);
+    assert_eq!(redo(), None);
+    assert_eq!(undo(), Some(Counter { count: 2 }));
+    assert_eq!(redo(), Some(Counter { count: 5 }));
+}
+
+#[test]
+fn audit_log_chains_each_change_to_the_one_before_and_redacts_sensitive_keys() {
+    let dir = TempDir::new();
+    let path = dir.0.join("audit.log");
+    let (syncer, _) = headless(StateSyncerConfig::builder().audit_log(&path).build());
+    syncer.set("counter", Counter { count: 1 });
+    syncer.update("counter", Counter { count: 2 }, false);
+    syncer.remove("counter");
+    syncer.mark_sensitive("token");
+    syncer.set(
+        "token",
+        Label {
+            text: "secret".to_string(),
+        },
+    );
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<_> = log.lines().collect();
+    assert_eq!(lines.len(), 4);
+    let field = |line: &str, name: &str| {
+        let prefix = format!("{}=", name);
+        line.split(' ')
+            .find_map(|part| part.strip_prefix(&prefix))
+            .unwrap()
+            .to_string()
+    };
+    assert_eq!(field(lines[0], "from"), "none");
+    assert_eq!(field(lines[0], "value"), r#"{"count":1}"#);
+    assert_eq!(field(lines[1], "from"), field(lines[0], "to"));
+    assert_eq!(field(lines[2], "from"), field(lines[1], "to"));
+    assert_eq!(field(lines[2], "value"), "<removed>");
+    assert_eq!(field(lines[3], "value"), "<redacted>");
+    assert!(!log.contains("secret"));
+}
+
+#[test]
+fn keys_past_their_ttl_read_as_missing_and_are_swept() {
+    let (syncer, sink) = headless(Default::default());
+    syncer
+        .set_with_ttl("session", Counter::default(), Duration::ZERO)
+        .unwrap();
+    syncer
+        .set_with_ttl("token", Counter::default(), Duration::ZERO)
+        .unwrap();
+    syncer
+        .set_with_ttl("cache", Counter::default(), Duration::from_secs(60))
+        .unwrap();
+
+    assert_eq!(syncer.try_snapshot::<Counter>("session"), None);
+    assert_eq!(
+        sink.events_for("session").last().unwrap().name,
+        "session_removed"
+    );
+    assert_eq!(syncer.sweep_expired(), ["token"]);
+    assert!(syncer.contains_key("cache"));
+
+    // a plain set clears the ttl
+    syncer
+        .set_with_ttl("cache", Counter::default(), Duration::ZERO)
+        .unwrap();
+    syncer.set("cache", Counter { count: 1 });
+    assert_eq!(syncer.sweep_expired(), Vec::<String>::new());
+    assert!(syncer.contains_key("cache"));
+}
+
+#[test]
+fn max_keys_evicts_the_least_recently_used_key_that_isnt_persisted() {
+    let cfg = StateSyncerConfig::builder()
+        .max_keys(3)
+        .persist_key("settings", true)
+        .build();
+    let (syncer, sink) = headless(cfg);
+    for key in ["settings", "first", "second", "third"] {
+        syncer.set(key, Counter::default());
+    }
+
+    // settings is the oldest but persisted, so first goes
+    assert!(syncer.contains_key("settings"));
+    assert!(!syncer.contains_key("first"));
+    assert_eq!(
+        sink.events_for("first").last().unwrap().name,
+        "first_removed"
+    );
+
+    // a read counts as a use
+    syncer.snapshot::<Counter>("second");
+    syncer.set("fourth", Counter::default());
+    assert!(syncer.contains_key("second"));
+    assert!(!syncer.contains_key("third"));
+}
+
+#[test]
+fn owned_handle_syncs_a_change_made_on_another_thread() {
+    let (syncer, sink) = headless(Default::default());
+    syncer.set("counter", Counter::default());
+
+    let handle = syncer.owned_handle::<Counter>("counter");
+    std::thread::spawn(move || {
+        handle.lock().unwrap().count = 3;
+        // dropped here, which emits the change
+    })
+    .join()
+    .unwrap();
+
+    assert_eq!(syncer.snapshot::<Counter>("counter"), Counter { count: 3 });
+    let events = sink.events_for("counter");
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].payload["value"]["count"], 3);
+
+    // a handle that wasn't written through syncs nothing
+    drop(syncer.owned_handle::<Counter>("counter"));
+    assert_eq!(sink.events_for("counter").len(), 1);
+}
+
+#[test]
+fn staged_item_is_only_stored_once_it_drops() {
+    let (syncer, sink) = headless(Default::default());
+    {
+        let draft = syncer.stage("draft", Label::default());
+        draft.lock().unwrap().text = "hello".to_string();
+        assert!(!syncer.contains_key("draft"));
+        assert!(sink.events().is_empty());
+    }
+
+    assert_eq!(
+        syncer.snapshot::<Label>("draft"),
+        Label {
+            text: "hello".to_string(),
+        }
+    );
+    let events = sink.events_for("draft");
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].payload["value"]["text"], "hello");
+}
+
+#[test]
+fn custom_serializer_shapes_the_store_file_and_export() {
+    let app = mock_app();
+    let dir = TempDir::new();
+    let syncer = on_disk(&app, &dir, StateSyncerConfig::builder());
+    // a Counter as a bare number
+    let to = |counter: &Counter| counter.count.to_string();
+    let from = |s: &str| s.parse().map(|count| Counter { count });
+    syncer.set_serializer("counter", to, from);
+    syncer.set("counter", Counter { count: 1 });
+    syncer.flush().unwrap();
+    assert_eq!(read_store(&dir)["counter"], 1);
+
+    let mut map = serde_json::Map::new();
+    map.insert("counter".to_string(), serde_json::json!(2));
+    syncer.import(map, false).unwrap();
+    assert_eq!(syncer.snapshot::<Counter>("counter"), Counter { count: 2 });
+    assert_eq!(syncer.export()["counter"], 2);
+
+    // read back through it
+    syncer.flush().unwrap();
+    drop(syncer);
+    let syncer = on_disk(&app, &dir, StateSyncerConfig::builder());
+    syncer.set_serializer("counter", to, from);
+    assert_eq!(syncer.load::<Counter>("counter"), Counter { count: 2 });
+}
+
+#[test]
+fn custom_serializer_shapes_the_events_sent() {
+    let (syncer, sink) = headless(Default::default());
+    syncer.set_serializer(
+        "counter",
+        |counter: &Counter| counter.count.to_string(),
+        |s: &str| s.parse().map(|count| Counter { count }),
+    );
+    syncer.set("counter", Counter::default());
+    syncer.update("counter", Counter { count: 4 }, true);
+    assert_eq!(sink.events_for("counter")[0].payload["value"], 4);
+}
+
+#[test]
+fn middleware_runs_in_order_and_can_reject_an_update() {
+    let (syncer, _) = headless(Default::default());
+    syncer.set("counter", Counter::default());
+    // clamp to 10, then refuse a reset to zero
+    syncer.add_middleware(|_, value| {
+        if value["count"].as_i64().is_some_and(|count| count > 10) {
+            value["count"] = 10.into();
+        }
+        MiddlewareResult::Continue
+    });
+    syncer.add_middleware(|key, value| match value["count"].as_i64() {
+        Some(0) => MiddlewareResult::Reject(format!("{} can't go back to zero", key)),
+        _ => MiddlewareResult::Continue,
+    });
+
+    syncer
+        .update_string("counter", r#"{ "count": 50 }"#, None, false)
+        .unwrap();
+    assert_eq!(syncer.snapshot::<Counter>("counter"), Counter { count: 10 });
+    assert!(matches!(
+        syncer.update_typed_string::<Counter>("counter", r#"{ "count": 0 }"#, None, false),
+        Err(StateError::Invalid(_, reason)) if reason == "counter can't go back to zero"
+    ));
+    assert_eq!(syncer.snapshot::<Counter>("counter"), Counter { count: 10 });
+}
+
+#[test]
+fn stale_update_is_rejected_unless_a_resolver_merges_it() {
+    let (syncer, _) = headless(Default::default());
+    syncer.set("counter", Counter { count: 1 });
+    let (_, seen) = syncer.snapshot_versioned::<Counter>("counter").unwrap();
+    syncer.update("counter", Counter { count: 2 }, false);
+
+    // made against the version before the last change
+    let stale = || syncer.try_update_versioned("counter", Counter { count: 5 }, Some(seen), false);
+    assert!(matches!(stale(), Err(StateError::Stale(_))));
+    assert_eq!(syncer.snapshot::<Counter>("counter"), Counter { count: 2 });
+
+    syncer.set_conflict_resolver("counter", |current: &Counter, incoming: &Counter| Counter {
+        count: current.count + incoming.count,
+    });
+    stale().unwrap();
+    assert_eq!(syncer.snapshot::<Counter>("counter"), Counter { count: 7 });
+    // the frontend path merges the same way
+    syncer
+        .update_string("counter", r#"{ "count": 1 }"#, Some(seen), false)
+        .unwrap();
+    assert_eq!(syncer.snapshot::<Counter>("counter"), Counter { count: 8 });
+}
+
+#[test]
+fn value_that_doesnt_fit_its_type_is_quarantined_not_lost() {
+    let dir = TempDir::new();
+    let stored = r#"{ "counter": { "count": "three" } }"#;
+    std::fs::write(dir.0.join("state.json"), stored).unwrap();
+
+    let app = mock_app();
+    let syncer = on_disk(&app, &dir, StateSyncerConfig::builder());
+    assert_eq!(syncer.load::<Counter>("counter"), Counter::default());
+    syncer.flush().unwrap();
+    assert_eq!(read_store(&dir)["counter"]["count"], 0);
+
+    // still there after the fallback was saved over it
+    assert_eq!(
+        syncer.quarantined("counter").unwrap(),
+        Some(serde_json::json!({ "count": "three" }))
+    );
+    syncer.clear_quarantined("counter").unwrap();
+    assert_eq!(syncer.quarantined("counter").unwrap(), None);
+    assert!(!dir.0.join("state.json.quarantine").exists());
+}
+
+#[test]
+fn value_that_doesnt_decrypt_is_quarantined_before_its_fallback_is_saved() {
+    let app = mock_app();
+    let dir = TempDir::new();
+    let encrypted = |key| StateSyncerConfig::builder().encryption(EncryptionConfig::from_key(key));
+    let syncer = on_disk(&app, &dir, encrypted([1; 32]));
+    syncer.set("counter", Counter { count: 3 });
+    syncer.flush().unwrap();
+    let sealed = read_store(&dir)["counter"].clone();
+    drop(syncer);
+
+    let app = mock_app();
+    let wrong_key = on_disk(&app, &dir, encrypted([2; 32]));
+    assert_eq!(wrong_key.load::<Counter>("counter"), Counter::default());
+    wrong_key.update("counter", Counter { count: 4 }, false);
+    wrong_key.flush().unwrap();
+    assert_ne!(read_store(&dir)["counter"], sealed);
+    drop(wrong_key);
+
+    // kept as it was stored, so the right key can still recover it
+    let app = mock_app();
+    let syncer = on_disk(&app, &dir, encrypted([1; 32]));
+    assert_eq!(
+        syncer.quarantined("counter").unwrap(),
+        Some(serde_json::json!({ "count": 3 }))
+    );
+}
+
+#[test]
+fn store_file_changed_on_disk_is_loaded_back_in() {
+    let app = mock_app();
+    let dir = TempDir::new();
+    let syncer = on_disk(&app, &dir, StateSyncerConfig::builder().watch_file(true));
+    syncer.set("counter", Counter { count: 1 });
+    syncer.set("label", Label::default());
+    syncer.flush().unwrap();
+
+    // edited by hand, after our own save so its modification time differs
+    std::thread::sleep(Duration::from_millis(20));
+    let edited = r#"{ "counter": { "count": 5 }, "label": { "text": "" } }"#;
+    std::fs::write(dir.0.join("state.json"), edited).unwrap();
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while syncer.snapshot::<Counter>("counter").count != 5 {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "edit was never loaded"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(syncer.snapshot::<Label>("label"), Label::default());
+}
+
+#[cfg(feature = "derive")]
+mod derived {
+    use super::*;
+
+    #[derive(crate::SyncedState)]
+    struct Settings {
+        counter: Counter,
+        #[synced(key = "title")]
+        label: Label,
+    }
+
+    #[test]
+    fn derived_state_syncs_each_field_as_a_key() {
+        assert_eq!(Settings::KEYS, ["counter", "title"]);
+
+        let (syncer, sink) = headless(StateSyncerConfig::default());
+        let settings = Settings {
+            counter: Counter { count: 1 },
+            label: Label {
+                text: "hi".to_string(),
+            },
+        };
+        settings.register(&syncer);
+        assert_eq!(syncer.snapshot::<Counter>("counter"), Counter { count: 1 });
+        assert_eq!(syncer.snapshot::<Label>("title").text, "hi");
+
+        assert!(Settings::emit(&syncer, "title"));
+        assert!(!Settings::emit(&syncer, "label"));
+        assert_eq!(sink.events_for("title").len(), 1);
+
+        let updated = Settings::update(&syncer, "counter", r#"{ "count": 3 }"#, None, false);
+        assert!(matches!(updated, Some(Ok(()))));
+        assert_eq!(syncer.snapshot::<Counter>("counter").count, 3);
+
+        let mistyped = Settings::update(&syncer, "counter", r#"{ "text": "" }"#, None, false);
+        assert!(matches!(mistyped, Some(Err(StateError::Deserialize(..)))));
+        assert!(Settings::update(&syncer, "label", "{}", None, false).is_none());
+    }
+}
@@ -0,0 +1,69 @@
+use std::any::Any;
+
+use serde_json::Value;
+
+use crate::codec::{Codec, CodecMap};
+use crate::{ItemTrait, Serializers, StateError};
+
+pub(crate) struct Staged {
+    pub(crate) key: String,
+    pub(crate) value: Box<dyn Any + Send>,
+    // what gets persisted and emitted once the transaction commits
+    pub(crate) json: Value,
+    pub(crate) serializers: Serializers,
+    // update requires the key to already hold the staged type, set may replace it
+    pub(crate) must_exist: bool,
+}
+
+// Txn collects the changes made in a StateSyncer::transaction. nothing it stages touches
+// the syncer until the transaction commits
+pub struct Txn {
+    pub(crate) staged: Vec<Staged>,
+    pub(crate) error: Option<StateError>,
+    // the syncer's, so staged values are serialized the way set_serializer says
+    codecs: CodecMap,
+}
+
+impl Txn {
+    pub(crate) fn new(codecs: CodecMap) -> Self {
+        Txn {
+            staged: Vec::new(),
+            error: None,
+            codecs,
+        }
+    }
+
+    pub fn set<T: ItemTrait>(&mut self, key: &str, value: T) {
+        self.stage(key, value, false);
+    }
+
+    // like set, but the transaction fails unless the key already holds a T
+    pub fn update<T: ItemTrait>(&mut self, key: &str, value: T) {
+        self.stage(key, value, true);
+    }
+
+    fn stage<T: ItemTrait>(&mut self, key: &str, value: T, must_exist: bool) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let codec = Codec::<T>::of(&self.codecs, key);
+        let json = match codec.encode(&value) {
+            Ok(json) => json,
+            Err(err) => {
+                self.error = Some(StateError::SerializeFailed(vec![(key.to_string(), err)]));
+                return;
+            }
+        };
+
+        // staging a key again replaces the earlier change
+        self.staged.retain(|staged| staged.key != key);
+        self.staged.push(Staged {
+            key: key.to_string(),
+            value: Box::new(value),
+            json,
+            serializers: Serializers::with_codec(codec),
+            must_exist,
+        });
+    }
+}
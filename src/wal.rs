@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::lock_recover;
+use crate::log::warn;
+
+// one change as it was handed to the store, a None value for a key that was deleted
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Record {
+    pub(crate) key: String,
+    pub(crate) value: Option<Value>,
+    pub(crate) version: u128,
+}
+
+// Wal appends each persisted change to "{path}.wal", synced before the store file is, so
+// changes the store file missed can be replayed after a crash
+#[derive(Clone)]
+pub(crate) struct Wal {
+    path: PathBuf,
+    // opened on the first append, reopened after compact replaces the file
+    file: Arc<Mutex<Option<File>>>,
+}
+
+impl Wal {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Wal {
+            path,
+            file: Default::default(),
+        }
+    }
+
+    pub(crate) fn append(&self, record: &Record) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let mut file = lock_recover(&self.file);
+        if file.is_none() {
+            *file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            );
+        }
+        let Some(file) = file.as_mut() else {
+            return Ok(());
+        };
+        file.write_all(&line)?;
+        file.sync_data()
+    }
+
+    // the latest record for each key, in the order they were last changed. a record cut
+    // short by a crash mid-append is skipped
+    pub(crate) fn latest(&self) -> io::Result<Vec<Record>> {
+        let _file = lock_recover(&self.file);
+        self.read()
+    }
+
+    // drop every record the store file has caught up with, given the value it now holds
+    pub(crate) fn compact<F: Fn(&str) -> Option<Value>>(&self, saved: F) -> io::Result<()> {
+        let mut file = lock_recover(&self.file);
+        let records = self.read()?;
+        let unsaved: Vec<_> = records
+            .into_iter()
+            .filter(|record| saved(&record.key) != record.value)
+            .collect();
+
+        // appends reopen whichever file is in place afterwards
+        *file = None;
+        if unsaved.is_empty() {
+            return self.remove();
+        }
+        let mut bytes = Vec::new();
+        for record in &unsaved {
+            serde_json::to_writer(&mut bytes, record)?;
+            bytes.push(b'\n');
+        }
+        crate::disk::write_atomic(&self.path, &bytes)
+    }
+
+    // forget every record, e.g. once the store has been cleared
+    pub(crate) fn clear(&self) -> io::Result<()> {
+        let mut file = lock_recover(&self.file);
+        *file = None;
+        self.remove()
+    }
+
+    fn remove(&self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn read(&self) -> io::Result<Vec<Record>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        let mut latest: HashMap<String, usize> = HashMap::new();
+        let mut records: Vec<Option<Record>> = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let record: Record = match serde_json::from_str(&line) {
+                Ok(record) => record,
+                Err(err) => {
+                    warn!(path = %self.path.display(), "skipping unreadable log record: {}", err);
+                    continue;
+                }
+            };
+            if let Some(previous) = latest.insert(record.key.clone(), records.len()) {
+                records[previous] = None;
+            }
+            records.push(Some(record));
+        }
+        Ok(records.into_iter().flatten().collect())
+    }
+}
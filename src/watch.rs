@@ -0,0 +1,48 @@
+use std::thread;
+use std::time::Duration;
+
+use tauri::Runtime;
+
+use crate::StateSyncer;
+use crate::log::{debug, error};
+
+// how often the store file's modification time is checked
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// reload the keys changed in the store file from outside, once it's stopped changing for
+// a poll. stops once only the watcher's handle is left
+pub(crate) fn spawn<R: Runtime>(syncer: StateSyncer<R>) {
+    let spawned = thread::Builder::new()
+        .name("state-syncer-watch".to_owned())
+        .spawn(move || run(syncer));
+    if let Err(err) = spawned {
+        error!("unable to spawn file watch thread: {}", err);
+    }
+}
+
+fn run<R: Runtime>(syncer: StateSyncer<R>) {
+    let mut seen = syncer.current_disk().and_then(|disk| disk.file.modified());
+    let mut changed = false;
+
+    while !syncer.is_watcher_only() {
+        thread::sleep(POLL_INTERVAL);
+
+        // looked up each time, set_store_file may have swapped it
+        let Some(file) = syncer.current_disk().map(|disk| disk.file) else {
+            continue;
+        };
+        let modified = file.modified();
+        if modified != seen {
+            seen = modified;
+            // our own saves aren't external changes
+            changed = !file.written_at(modified);
+            continue;
+        }
+
+        if changed {
+            changed = false;
+            debug!("store file changed on disk, reloading");
+            syncer.reload_file();
+        }
+    }
+}